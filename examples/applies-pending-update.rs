@@ -0,0 +1,21 @@
+use std::env::consts::EXE_EXTENSION;
+
+fn main() {
+    if self_replace::apply_pending_update().unwrap() {
+        println!("Applied pending update");
+        return;
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("stage") {
+        let exe = std::env::current_exe().unwrap();
+        let new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+        if !new_executable.is_file() {
+            eprintln!("hello does not exist, run cargo build --example hello first.");
+            std::process::exit(1);
+        }
+        self_replace::stage_update(&new_executable).unwrap();
+        println!("Next time I run, I become the hello executable");
+    } else {
+        println!("Nothing to do");
+    }
+}
@@ -0,0 +1,13 @@
+use self_replace::DeleteOptions;
+
+fn main() {
+    // when invoked through a symlink, the first argument is the symlink's path
+    let invoked_as = std::env::args_os().next().unwrap();
+    println!("When I finish, the link I was invoked through is deleted");
+    self_replace::self_delete_with(&DeleteOptions::new().executable(invoked_as).link_only(true))
+        .unwrap();
+
+    if std::env::var("FORCE_EXIT").ok().as_deref() == Some("1") {
+        std::process::exit(0);
+    }
+}
@@ -0,0 +1,12 @@
+use self_replace::DeleteOptions;
+
+fn main() {
+    println!("When I exit, I am deleted");
+    let exe = std::env::current_exe().unwrap();
+    self_replace::self_delete_with(&DeleteOptions::new().deferred(true)).unwrap();
+    assert!(exe.is_file());
+
+    if std::env::var("FORCE_EXIT").ok().as_deref() == Some("1") {
+        std::process::exit(0);
+    }
+}
@@ -0,0 +1,17 @@
+use self_replace::DeleteOptions;
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let links = exe.parent().unwrap().parent().unwrap().join("links");
+    let outcome =
+        self_replace::self_delete_with(&DeleteOptions::new().remove_links(true).search_dir(links))
+            .unwrap();
+    println!(
+        "When I finish, I am deleted with {} links",
+        outcome.removed_links.len()
+    );
+
+    if std::env::var("FORCE_EXIT").ok().as_deref() == Some("1") {
+        std::process::exit(0);
+    }
+}
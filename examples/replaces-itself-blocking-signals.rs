@@ -0,0 +1,33 @@
+use std::env::consts::EXE_EXTENSION;
+use std::thread;
+use std::time::Duration;
+
+use self_replace::{ReplaceOptions, RetryPolicy};
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !new_executable.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    // retrying a busy executable keeps us in the commit for a while, during which
+    // termination signals are held back.
+    let retry = RetryPolicy::new()
+        .max_attempts(15)
+        .backoff(Duration::from_millis(200))
+        .max_backoff(Duration::from_millis(200));
+    let options = ReplaceOptions::new().block_signals(true).retry(retry);
+
+    // signals sent to the process are held back for other threads too.
+    thread::spawn(|| loop {
+        thread::sleep(Duration::from_secs(60));
+    });
+    println!("Committing");
+    match self_replace::self_replace_with(&new_executable, &options) {
+        Ok(_) => println!("Next time I run, I am the hello executable"),
+        Err(err) => println!("Not replaced: {err}"),
+    }
+}
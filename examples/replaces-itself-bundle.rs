@@ -0,0 +1,17 @@
+use std::env::consts::EXE_EXTENSION;
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let update = exe.parent().unwrap().join("update");
+
+    println!("Next time I run, I am the hello executable with new data");
+    self_replace::Bundle::new()
+        .executable(update.join("hello").with_extension(EXE_EXTENSION))
+        .file(exe.with_file_name("data.txt"), update.join("data.txt"))
+        .commit()
+        .unwrap();
+
+    if std::env::var("FORCE_EXIT").ok().as_deref() == Some("1") {
+        std::process::exit(0);
+    }
+}
@@ -0,0 +1,30 @@
+use std::env::consts::EXE_EXTENSION;
+
+use self_replace::ReplaceOptions;
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let hello = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !hello.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    // the update is downloaded next to the executable (unless another folder is
+    // given), so it can be moved in place
+    let new_executable = match std::env::args_os().nth(1) {
+        Some(dir) => std::path::Path::new(&dir).join("update"),
+        None => exe.with_file_name("update"),
+    }
+    .with_extension(EXE_EXTENSION);
+    std::fs::copy(&hello, &new_executable).unwrap();
+
+    let options = ReplaceOptions::new().consume_source(true);
+    let outcome = self_replace::self_replace_with(&new_executable, &options).unwrap();
+    println!(
+        "Next time I run, I am the hello executable ({:?}, update left: {})",
+        outcome.staging,
+        new_executable.exists()
+    );
+}
@@ -0,0 +1,24 @@
+use std::env::consts::EXE_EXTENSION;
+
+use self_replace::{ReplaceOptions, RetryPolicy};
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !new_executable.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    let options = ReplaceOptions::new()
+        .hardened(true)
+        .verify(true)
+        .retry(RetryPolicy::new());
+    self_replace::self_replace_with(&new_executable, &options).unwrap();
+    println!("Next time I run, I am the hello executable");
+
+    if std::env::var("FORCE_EXIT").ok().as_deref() == Some("1") {
+        std::process::exit(0);
+    }
+}
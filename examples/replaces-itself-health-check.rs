@@ -0,0 +1,33 @@
+use self_replace::{HealthStatus, ReplaceOptions};
+
+fn main() {
+    match self_replace::check_health().unwrap() {
+        HealthStatus::Healthy => {}
+        HealthStatus::Unconfirmed {
+            launches,
+            max_launches,
+        } => println!("Unconfirmed launch {launches} of {max_launches}"),
+        HealthStatus::RolledBack { .. } => {
+            println!("Rolled back to the previous version");
+            return;
+        }
+        _ => unreachable!(),
+    }
+
+    let mut args = std::env::args_os().skip(1);
+    match args.next().as_ref().and_then(|x| x.to_str()) {
+        Some("update") => {
+            let new_executable = args.next().expect("missing new executable");
+            let options = ReplaceOptions::new()
+                .running_version("1.0.0")
+                .health_check(2);
+            self_replace::self_replace_with(new_executable, &options).unwrap();
+            println!("Updated, the next version needs to confirm its health");
+        }
+        Some("confirm") => {
+            self_replace::mark_healthy().unwrap();
+            println!("Confirmed");
+        }
+        _ => println!("Running"),
+    }
+}
@@ -0,0 +1,25 @@
+use std::env::consts::EXE_EXTENSION;
+
+use self_replace::ReplaceOptions;
+
+fn main() {
+    self_replace::handle_update_helper();
+
+    let exe = std::env::current_exe().unwrap();
+    let new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !new_executable.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    let options = ReplaceOptions::new().in_place_fallback(true);
+    let outcome = self_replace::self_replace_with(&new_executable, &options).unwrap();
+    if outcome.deferred {
+        println!("After I exit, I am overwritten with the hello executable");
+    } else if outcome.in_place {
+        println!("Next time I run, I am overwritten with the hello executable");
+    } else {
+        println!("Next time I run, I am the hello executable");
+    }
+}
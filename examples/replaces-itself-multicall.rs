@@ -0,0 +1,32 @@
+use std::env::consts::EXE_EXTENSION;
+
+use self_replace::ReplaceOptions;
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !new_executable.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    // the applets the "new version" supports
+    let mut options = ReplaceOptions::new().applet_links(["tool-foo", "tool-baz", "tool-new"]);
+    if let Some(dir) = std::env::args_os().nth(1) {
+        options = options.applet_dir(dir);
+    }
+    let outcome = self_replace::self_replace_with(&new_executable, &options).unwrap();
+    println!(
+        "Next time I run, I am the hello executable with {} new and {} removed applets",
+        outcome.applets_added.len(),
+        outcome.applets_removed.len()
+    );
+    if let Some(err) = outcome.applet_error {
+        println!("Applets not reconciled: {err}");
+    }
+
+    if std::env::var("FORCE_EXIT").ok().as_deref() == Some("1") {
+        std::process::exit(0);
+    }
+}
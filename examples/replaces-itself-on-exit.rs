@@ -0,0 +1,33 @@
+use std::env::consts::EXE_EXTENSION;
+use std::time::Duration;
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !new_executable.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    self_replace::self_replace_on_exit(&new_executable).unwrap();
+    assert!(self_replace::is_self_replace_pending());
+
+    match std::env::args().nth(1).as_deref() {
+        Some("cancel") => {
+            assert!(self_replace::cancel_self_replace());
+            println!("I changed my mind, I stay who I am");
+        }
+        Some("wait") => {
+            println!("When I am interrupted, I am the hello executable");
+            loop {
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        }
+        _ => println!("When I exit, I am the hello executable"),
+    }
+
+    if std::env::var("FORCE_EXIT").ok().as_deref() == Some("1") {
+        std::process::exit(0);
+    }
+}
@@ -0,0 +1,48 @@
+use std::env::consts::EXE_EXTENSION;
+use std::fs;
+use std::io::Write;
+
+use self_replace::{ProgressPhase, ReplaceOptions};
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let hello = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !hello.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    // the new executable is truncated or corrupted behind our back once it was
+    // staged, so that the staged copy no longer matches.
+    let new_executable = exe.with_file_name("source");
+    fs::copy(&hello, &new_executable).unwrap();
+    let mode = std::env::args().nth(1).unwrap_or_default();
+    let options = ReplaceOptions::new()
+        .verify(mode == "corrupt")
+        .hardened(std::env::args().any(|x| x == "hardened"))
+        .progress({
+            let new_executable = new_executable.clone();
+            move |progress| {
+                if progress.phase != ProgressPhase::Staging || progress.done != progress.total {
+                    return;
+                }
+                let mut file = fs::OpenOptions::new()
+                    .write(true)
+                    .open(&new_executable)
+                    .unwrap();
+                match mode.as_str() {
+                    "truncate" => file.set_len(progress.total - 1).unwrap(),
+                    "corrupt" => file.write_all(b"\0").unwrap(),
+                    _ => {}
+                }
+            }
+        });
+
+    let rv = self_replace::self_replace_with(&new_executable, &options);
+    fs::remove_file(&new_executable).unwrap();
+    match rv {
+        Ok(_) => println!("Next time I run, I am the hello executable"),
+        Err(err) => println!("Not replaced: {err}"),
+    }
+}
@@ -0,0 +1,28 @@
+use std::env::consts::EXE_EXTENSION;
+
+use self_replace::ReplaceOptions;
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let mut new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !new_executable.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    let mut args = std::env::args_os().skip(1);
+    let log = args.next().expect("path to the audit log");
+    if args.next().as_deref() == Some("missing".as_ref()) {
+        new_executable.set_file_name("missing");
+    }
+
+    let options = ReplaceOptions::new()
+        .running_version("1.0")
+        .new_version("2.0")
+        .audit_log(log);
+    match self_replace::self_replace_with(&new_executable, &options) {
+        Ok(_) => println!("Next time I run, I am the hello executable"),
+        Err(_) => println!("Not replaced"),
+    }
+}
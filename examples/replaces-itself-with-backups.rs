@@ -0,0 +1,28 @@
+use self_replace::{ReplaceOptions, RetentionPolicy};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("update") => {
+            let new_executable = args.next().expect("missing new executable");
+            let version = args.next().expect("missing version");
+            let options = ReplaceOptions::new()
+                .backup(true)
+                .verify(true)
+                .running_version(version)
+                .retention(RetentionPolicy::new().keep_last(2));
+            let outcome = self_replace::self_replace_with(new_executable, &options).unwrap();
+            println!("Updated, pruned {} backups", outcome.pruned_backups.len());
+        }
+        Some("prune") => {
+            let removed =
+                self_replace::prune_backups(&RetentionPolicy::new().keep_last(1)).unwrap();
+            println!("Pruned {} backups", removed.len());
+        }
+        _ => {
+            for backup in self_replace::list_backups().unwrap() {
+                println!("{}", backup.version.as_deref().unwrap_or("unknown"));
+            }
+        }
+    }
+}
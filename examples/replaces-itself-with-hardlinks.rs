@@ -0,0 +1,32 @@
+use std::env::consts::EXE_EXTENSION;
+
+use self_replace::ReplaceOptions;
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !new_executable.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    let mut options = ReplaceOptions::new()
+        .update_hardlinks(true)
+        .block_signals(true);
+    for dir in std::env::args_os().skip(1) {
+        options = options.search_dir(dir);
+    }
+    let outcome = self_replace::self_replace_with(&new_executable, &options).unwrap();
+    println!(
+        "Next time I run, I and {} hardlinks are the hello executable",
+        outcome.hardlinks.len() - outcome.hardlink_errors.len()
+    );
+    for (link, err) in &outcome.hardlink_errors {
+        println!("Not updated: {}: {}", link.display(), err);
+    }
+
+    if std::env::var("FORCE_EXIT").ok().as_deref() == Some("1") {
+        std::process::exit(0);
+    }
+}
@@ -0,0 +1,49 @@
+use std::env::consts::EXE_EXTENSION;
+use std::io;
+
+use self_replace::{HookContext, HookOutcome, Hooks, ReplaceOptions};
+
+struct PrintHooks {
+    veto: bool,
+}
+
+impl Hooks for PrintHooks {
+    fn pre_stage(&self, ctx: &HookContext<'_>) -> Result<(), io::Error> {
+        println!("pre_stage {:?}", ctx.operation);
+        Ok(())
+    }
+
+    fn pre_commit(&self, _ctx: &HookContext<'_>) -> Result<(), io::Error> {
+        println!("pre_commit");
+        if self.veto {
+            return Err(io::Error::new(io::ErrorKind::Other, "vetoed"));
+        }
+        Ok(())
+    }
+
+    fn post_commit(&self, _ctx: &HookContext<'_>, outcome: HookOutcome<'_>) {
+        if let HookOutcome::Replace(_) = outcome {
+            println!("post_commit");
+        }
+    }
+
+    fn on_error(&self, _ctx: &HookContext<'_>, err: &io::Error) {
+        println!("on_error {err}");
+    }
+}
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !new_executable.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    let veto = std::env::args().nth(1).as_deref() == Some("veto");
+    let options = ReplaceOptions::new().hooks(PrintHooks { veto });
+    if self_replace::self_replace_with(&new_executable, &options).is_ok() {
+        println!("Next time I run, I am the hello executable");
+    }
+}
@@ -0,0 +1,42 @@
+use std::env::consts::EXE_EXTENSION;
+use std::sync::{Arc, Mutex};
+
+use self_replace::{CancellationToken, ProgressPhase, ReplaceOptions};
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !new_executable.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    // a real application would cancel from another thread, for instance when the
+    // user presses a key.  Here we give up as soon as verifying starts.
+    let cancel = std::env::args().nth(1).as_deref() == Some("cancel");
+    let token = CancellationToken::new();
+    let last_staged = Arc::new(Mutex::new(None));
+    let options = ReplaceOptions::new()
+        .verify(true)
+        .cancellation(token.clone())
+        .progress({
+            let last_staged = last_staged.clone();
+            move |progress| match progress.phase {
+                ProgressPhase::Staging => {
+                    *last_staged.lock().unwrap() = Some((progress.done, progress.total))
+                }
+                ProgressPhase::Verifying if cancel => token.cancel(),
+                _ => {}
+            }
+        });
+
+    match self_replace::self_replace_with(&new_executable, &options) {
+        Ok(_) => {
+            let (done, total) = last_staged.lock().unwrap().unwrap();
+            assert_eq!(done, total);
+            println!("Next time I run, I am the hello executable");
+        }
+        Err(err) => println!("Not replaced: {err}"),
+    }
+}
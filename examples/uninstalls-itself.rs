@@ -0,0 +1,16 @@
+use self_replace::UninstallManifest;
+
+fn main() {
+    let exe = std::env::current_exe().unwrap();
+    let manifest = UninstallManifest::load(exe.with_file_name("uninstall.manifest")).unwrap();
+    let report = self_replace::self_uninstall(&manifest).unwrap();
+    println!(
+        "When I finish, I removed {} entries and kept {} modified files",
+        report.removed.len(),
+        report.modified.len()
+    );
+
+    if std::env::var("FORCE_EXIT").ok().as_deref() == Some("1") {
+        std::process::exit(0);
+    }
+}
@@ -0,0 +1,18 @@
+use std::env::consts::EXE_EXTENSION;
+
+fn main() {
+    self_replace::handle_update_helper();
+
+    let exe = std::env::current_exe().unwrap();
+    let new_executable = exe.with_file_name("hello").with_extension(EXE_EXTENSION);
+
+    if !new_executable.is_file() {
+        eprintln!("hello does not exist, run cargo build --example hello first.");
+        std::process::exit(1);
+    }
+
+    println!("Once I exited, I am the hello executable");
+    self_replace::UpdateHelper::new(&new_executable)
+        .spawn()
+        .unwrap();
+}
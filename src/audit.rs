@@ -0,0 +1,202 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::hash::sha256_file;
+
+/// The kind of operation recorded in an [`AuditEntry`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum AuditOperation {
+    /// The executable was replaced with [`self_replace_with`](crate::self_replace_with).
+    Replace,
+    /// A failed update was rolled back by [`check_health`](crate::check_health).
+    Rollback,
+    /// The executable was deleted with [`self_delete_with`](crate::self_delete_with).
+    Delete,
+}
+
+/// An entry of the audit log, see [`ReplaceOptions::audit_log`].
+///
+/// [`ReplaceOptions::audit_log`]: crate::ReplaceOptions::audit_log
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
+pub struct AuditEntry {
+    /// When the operation finished.
+    ///
+    /// This is serialized as milliseconds since the epoch, like in the log.
+    #[serde(with = "crate::timestamp::millis")]
+    pub timestamp: SystemTime,
+    /// What was done.
+    pub operation: AuditOperation,
+    /// The path of the executable.
+    pub executable: PathBuf,
+    /// The hex encoded SHA-256 digest of the executable before the operation.
+    pub old_sha256: Option<String>,
+    /// The hex encoded SHA-256 digest of the executable that was put in place.
+    pub new_sha256: Option<String>,
+    /// The version that was running, if known.
+    pub old_version: Option<String>,
+    /// The version that was put in place, if known.
+    pub new_version: Option<String>,
+    /// The user that ran the operation.
+    ///
+    /// This is taken from `USER` (`USERNAME` on Windows), on Unix falling back to
+    /// the name of the current user id.
+    pub user: Option<String>,
+    /// The error message if the operation failed.
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    /// Returns `true` if the operation succeeded.
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Reads all entries of an audit log, oldest first.
+///
+/// A log that does not exist yet has no entries.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// for entry in self_replace::history("/var/log/tool/updates.jsonl")? {
+///     println!("{:?} {:?} {:?}", entry.timestamp, entry.operation, entry.new_version);
+/// }
+/// # Ok(()) }
+/// ```
+pub fn history<P: AsRef<Path>>(log: P) -> Result<Vec<AuditEntry>, io::Error> {
+    let log = log.as_ref();
+    let contents = match fs::read_to_string(log) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut rv = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        rv.push(serde_json::from_str(line).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "invalid audit entry in {}:{}: {err}",
+                    log.display(),
+                    idx + 1
+                ),
+            )
+        })?);
+    }
+    Ok(rv)
+}
+
+/// An audit log that is being written to.
+///
+/// The log is opened before the operation starts so that an operation that cannot
+/// be recorded does not happen at all.
+pub(crate) struct AuditLog {
+    file: fs::File,
+    entry: AuditEntry,
+}
+
+impl AuditLog {
+    /// Opens the log and records the state before the operation.
+    pub fn open(
+        log: &Path,
+        operation: AuditOperation,
+        exe: &Path,
+        new_executable: Option<&Path>,
+    ) -> Result<AuditLog, io::Error> {
+        let file = fs::OpenOptions::new().create(true).append(true).open(log)?;
+        Ok(AuditLog {
+            file,
+            entry: AuditEntry {
+                timestamp: UNIX_EPOCH,
+                operation,
+                executable: exe.to_path_buf(),
+                old_sha256: sha256_file(exe).ok(),
+                new_sha256: new_executable.and_then(|x| sha256_file(x).ok()),
+                old_version: None,
+                new_version: None,
+                user: current_user(),
+                error: None,
+            },
+        })
+    }
+
+    /// Sets the versions before and after the operation.
+    pub fn versions(mut self, old: Option<&str>, new: Option<&str>) -> AuditLog {
+        self.entry.old_version = old.map(|x| x.to_string());
+        self.entry.new_version = new.map(|x| x.to_string());
+        self
+    }
+
+    /// Appends the entry for the finished operation.
+    ///
+    /// Failing to write the entry does not undo the operation, so the error is
+    /// not reported.
+    pub fn finish<T>(mut self, result: &Result<T, io::Error>) {
+        self.entry.timestamp = SystemTime::now();
+        if let Err(ref err) = *result {
+            self.entry.error = Some(err.to_string());
+        }
+        let line = Line {
+            entry: &self.entry,
+            outcome: if self.entry.succeeded() {
+                "ok"
+            } else {
+                "error"
+            },
+        };
+        if let Ok(mut line) = serde_json::to_vec(&line) {
+            line.push(b'\n');
+            // a single write keeps concurrent appends from interleaving.
+            self.file.write_all(&line).ok();
+        }
+    }
+}
+
+/// A line of the log, which spells out the outcome for readers other than
+/// [`history`].
+#[derive(serde::Serialize)]
+struct Line<'a> {
+    #[serde(flatten)]
+    entry: &'a AuditEntry,
+    outcome: &'static str,
+}
+
+fn current_user() -> Option<String> {
+    let var = if cfg!(windows) { "USERNAME" } else { "USER" };
+    let user = env::var(var).ok().filter(|x| !x.is_empty());
+    #[cfg(unix)]
+    {
+        user.or_else(user_name_from_uid)
+    }
+    #[cfg(not(unix))]
+    {
+        user
+    }
+}
+
+/// Looks up the name of the current user id, or returns the id if it has none.
+#[cfg(unix)]
+fn user_name_from_uid() -> Option<String> {
+    use std::ffi::CStr;
+
+    let uid = unsafe { libc::getuid() };
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0; 4096];
+    let rv = unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rv == 0 && !result.is_null() {
+        if let Ok(name) = unsafe { CStr::from_ptr(pwd.pw_name) }.to_str() {
+            return Some(name.to_string());
+        }
+    }
+    Some(uid.to_string())
+}
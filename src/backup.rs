@@ -0,0 +1,363 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::fsutil::{current_exe, sibling_path};
+use crate::hash::sha256_file;
+
+/// A backup of a previous version of the executable.
+///
+/// Backups are kept when updating with [`ReplaceOptions::backup`] and can be
+/// listed with [`list_backups`].
+///
+/// [`ReplaceOptions::backup`]: crate::ReplaceOptions::backup
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Backup {
+    /// The path of the backed up executable.
+    pub path: PathBuf,
+    /// The version recorded with [`ReplaceOptions::running_version`].
+    ///
+    /// [`ReplaceOptions::running_version`]: crate::ReplaceOptions::running_version
+    pub version: Option<String>,
+    /// The hex encoded SHA-256 digest of the executable.
+    pub sha256: String,
+    /// When the backup was made.
+    ///
+    /// This is serialized as milliseconds since the epoch.
+    #[cfg_attr(feature = "serde", serde(with = "crate::timestamp::millis"))]
+    pub created: SystemTime,
+    /// The size of the executable in bytes.
+    pub size: u64,
+}
+
+/// Decides which backups [`prune_backups`] keeps.
+///
+/// A backup is kept if it's one of the [`keep_last`](Self::keep_last) most
+/// recent ones or younger than [`keep_younger_than`](Self::keep_younger_than).
+/// If neither is set, all backups are kept.  On top of that, the oldest of the
+/// kept backups are removed until they take up no more than
+/// [`max_total_bytes`](Self::max_total_bytes).
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// use std::time::Duration;
+/// use self_replace::RetentionPolicy;
+/// let policy = RetentionPolicy::new()
+///     .keep_last(3)
+///     .keep_younger_than(Duration::from_secs(30 * 24 * 60 * 60))
+///     .max_total_bytes(500 * 1024 * 1024);
+/// self_replace::prune_backups(&policy)?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetentionPolicy {
+    keep_last: Option<usize>,
+    keep_younger_than: Option<Duration>,
+    max_total_bytes: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Creates a policy which keeps all backups.
+    pub fn new() -> RetentionPolicy {
+        RetentionPolicy::default()
+    }
+
+    /// Keeps the `n` most recent backups.
+    pub fn keep_last(mut self, n: usize) -> RetentionPolicy {
+        self.keep_last = Some(n);
+        self
+    }
+
+    /// Keeps all backups younger than `age`.
+    pub fn keep_younger_than(mut self, age: Duration) -> RetentionPolicy {
+        self.keep_younger_than = Some(age);
+        self
+    }
+
+    /// Limits the total size of all backups.
+    pub fn max_total_bytes(mut self, bytes: u64) -> RetentionPolicy {
+        self.max_total_bytes = Some(bytes);
+        self
+    }
+
+    /// Splits the backups (newest first) into the ones to keep and to remove.
+    fn apply(&self, backups: Vec<Backup>, now: SystemTime) -> (Vec<Backup>, Vec<Backup>) {
+        let mut keep = Vec::new();
+        let mut remove = Vec::new();
+        for (idx, backup) in backups.into_iter().enumerate() {
+            let recent = self.keep_last.map_or(false, |n| idx < n);
+            let young = self.keep_younger_than.map_or(false, |age| {
+                now.duration_since(backup.created)
+                    .map_or(true, |elapsed| elapsed < age)
+            });
+            let unrestricted = self.keep_last.is_none() && self.keep_younger_than.is_none();
+            if unrestricted || recent || young {
+                keep.push(backup);
+            } else {
+                remove.push(backup);
+            }
+        }
+        if let Some(max_total_bytes) = self.max_total_bytes {
+            let mut total: u64 = keep.iter().map(|x| x.size).sum();
+            while total > max_total_bytes {
+                match keep.pop() {
+                    Some(backup) => {
+                        total -= backup.size;
+                        remove.push(backup);
+                    }
+                    None => break,
+                }
+            }
+        }
+        (keep, remove)
+    }
+}
+
+/// Lists the backups of the current executable, newest first.
+pub fn list_backups() -> Result<Vec<Backup>, io::Error> {
+    read_backups(&backups_dir(&current_exe()?)?)
+}
+
+/// Removes the backups of the current executable which the policy does not keep.
+///
+/// The backup that an unconfirmed update (see [`check_health`]) would roll back
+/// to is never removed.  Returns the removed backups.
+///
+/// [`check_health`]: crate::check_health
+pub fn prune_backups(policy: &RetentionPolicy) -> Result<Vec<Backup>, io::Error> {
+    prune(&current_exe()?, policy, None)
+}
+
+/// Prunes the backups of `exe`, never removing `protected`.
+pub(crate) fn prune(
+    exe: &Path,
+    policy: &RetentionPolicy,
+    protected: Option<&Path>,
+) -> Result<Vec<Backup>, io::Error> {
+    let pending = crate::health::pending_backup(exe)?;
+    let backups = read_backups(&backups_dir(exe)?)?;
+    let (_, remove) = policy.apply(backups, SystemTime::now());
+    let mut removed = Vec::new();
+    for backup in remove {
+        if Some(&backup.path) == pending.as_ref() || Some(backup.path.as_path()) == protected {
+            continue;
+        }
+        remove_backup(&backup.path)?;
+        removed.push(backup);
+    }
+    Ok(removed)
+}
+
+pub(crate) fn read_backups(dir: &Path) -> Result<Vec<Backup>, io::Error> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+    let mut rv = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().map_or(false, |x| x == "meta") {
+            continue;
+        }
+        // files without metadata are not backups (or incomplete ones)
+        let meta = match fs::read_to_string(meta_path(&path)) {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        let mut version = None;
+        let mut sha256 = None;
+        let mut created = None;
+        for line in meta.lines() {
+            let (key, value) = match line.find(' ') {
+                Some(idx) => (&line[..idx], &line[idx + 1..]),
+                None => (line, ""),
+            };
+            match key {
+                "version" => version = Some(value.to_string()),
+                "sha256" => sha256 = Some(value.to_string()),
+                "created" => created = value.parse().ok().map(crate::timestamp::from_millis),
+                _ => {}
+            }
+        }
+        if let (Some(sha256), Some(created)) = (sha256, created) {
+            let size = fs::metadata(&path)?.len();
+            rv.push(Backup {
+                path,
+                version,
+                sha256,
+                created,
+                size,
+            });
+        }
+    }
+    rv.sort_by(|a, b| (b.created, &b.path).cmp(&(a.created, &a.path)));
+    Ok(rv)
+}
+
+/// Returns the folder in which backups of the executable are kept.
+///
+/// This is a hidden folder next to the executable named `.{name}.backups`.
+pub fn backups_dir(exe: &Path) -> Result<PathBuf, io::Error> {
+    sibling_path(exe, "backups")
+}
+
+/// Keeps a copy of the executable in the backups folder.
+///
+/// The backup is named `{timestamp in ms}-{digest prefix}` and accompanied by a `.meta`
+/// file that records the version, the digest and the time of the backup.  If `link`
+/// is set the backup is a hardlink where possible, as replacing the executable puts
+/// a new file in its place anyways.  This must not be used if the executable might
+/// be overwritten in place instead, as that would overwrite the backup too.
+pub fn create_backup(exe: &Path, version: Option<&str>, link: bool) -> Result<PathBuf, io::Error> {
+    if version.map_or(false, |x| x.contains('\n')) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "version must not contain newlines",
+        ));
+    }
+    let dir = backups_dir(exe)?;
+    fs::create_dir_all(&dir)?;
+    let sha256 = sha256_file(exe)?;
+    let timestamp = crate::timestamp::to_millis(SystemTime::now());
+
+    let path = dir.join(format!("{}-{}", timestamp, &sha256[..16]));
+    let exists = fs::symlink_metadata(&path).is_ok();
+    let present = exists || (link && fs::hard_link(exe, &path).is_ok());
+    if !present {
+        if let Err(err) = fs::copy(exe, &path) {
+            fs::remove_file(&path).ok();
+            return Err(err);
+        }
+    }
+
+    let mut meta = String::new();
+    if let Some(version) = version {
+        meta.push_str(&format!("version {version}\n"));
+    }
+    meta.push_str(&format!("sha256 {sha256}\ncreated {timestamp}\n"));
+    if let Err(err) = fs::write(meta_path(&path), meta) {
+        fs::remove_file(&path).ok();
+        return Err(err);
+    }
+    Ok(path)
+}
+
+/// Removes a backup together with its metadata.
+pub fn remove_backup(path: &Path) -> Result<(), io::Error> {
+    fs::remove_file(path)?;
+    fs::remove_file(meta_path(path)).ok();
+    Ok(())
+}
+
+fn meta_path(path: &Path) -> PathBuf {
+    path.with_extension("meta")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    /// Backups made every hour with the given sizes, newest first.
+    fn backups(now: SystemTime, sizes: &[u64]) -> Vec<Backup> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(idx, &size)| Backup {
+                path: PathBuf::from(idx.to_string()),
+                version: None,
+                sha256: String::new(),
+                created: now - HOUR * idx as u32,
+                size,
+            })
+            .collect()
+    }
+
+    fn paths(backups: &[Backup]) -> Vec<&str> {
+        backups.iter().map(|x| x.path.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_unrestricted() {
+        let now = SystemTime::now();
+        let (keep, remove) = RetentionPolicy::new().apply(backups(now, &[1, 1, 1]), now);
+        assert_eq!(paths(&keep), ["0", "1", "2"]);
+        assert!(remove.is_empty());
+    }
+
+    #[test]
+    fn test_keep_younger_than() {
+        let now = SystemTime::now();
+        let policy = RetentionPolicy::new().keep_younger_than(HOUR * 2);
+        let (keep, remove) = policy.apply(backups(now, &[1, 1, 1, 1]), now);
+        assert_eq!(paths(&keep), ["0", "1"]);
+        assert_eq!(paths(&remove), ["2", "3"]);
+
+        // backups from the future (clock changes) are young
+        let (keep, _) = policy.apply(backups(now, &[1]), now - HOUR);
+        assert_eq!(paths(&keep), ["0"]);
+    }
+
+    #[test]
+    fn test_keep_last_or_younger_than() {
+        let now = SystemTime::now();
+        let (keep, remove) = RetentionPolicy::new()
+            .keep_last(3)
+            .keep_younger_than(HOUR * 2)
+            .apply(backups(now, &[1, 1, 1, 1, 1]), now);
+        assert_eq!(paths(&keep), ["0", "1", "2"]);
+        assert_eq!(paths(&remove), ["3", "4"]);
+
+        let (keep, remove) = RetentionPolicy::new()
+            .keep_last(1)
+            .keep_younger_than(HOUR * 3)
+            .apply(backups(now, &[1, 1, 1, 1, 1]), now);
+        assert_eq!(paths(&keep), ["0", "1", "2"]);
+        assert_eq!(paths(&remove), ["3", "4"]);
+    }
+
+    #[test]
+    fn test_max_total_bytes() {
+        let now = SystemTime::now();
+        let (keep, remove) = RetentionPolicy::new()
+            .max_total_bytes(25)
+            .apply(backups(now, &[10, 10, 10, 10]), now);
+        assert_eq!(paths(&keep), ["0", "1"]);
+        assert_eq!(paths(&remove), ["3", "2"]);
+
+        // the size limit applies on top of the other rules, even if that means
+        // that nothing is kept
+        let (keep, remove) = RetentionPolicy::new()
+            .keep_last(3)
+            .max_total_bytes(5)
+            .apply(backups(now, &[10, 1, 1, 1]), now);
+        assert!(keep.is_empty());
+        assert_eq!(paths(&remove), ["3", "2", "1", "0"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_create_backup_link() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("exe");
+        fs::write(&exe, "old").unwrap();
+        let ino = |path: &Path| fs::metadata(path).unwrap().ino();
+
+        let linked = create_backup(&exe, None, true).unwrap();
+        assert_eq!(ino(&linked), ino(&exe));
+        remove_backup(&linked).unwrap();
+
+        // backups must not share the inode if the executable may be written in place.
+        let copied = create_backup(&exe, None, false).unwrap();
+        assert_ne!(ino(&copied), ino(&exe));
+        assert_eq!(fs::read(&copied).unwrap(), b"old");
+    }
+}
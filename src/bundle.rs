@@ -0,0 +1,226 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::fsutil::{current_exe, sibling_temp_path};
+
+/// Replaces a set of files as one unit.
+///
+/// Applications which ship as an executable plus resources that must match the
+/// version of the executable (plugins, shared libraries, data files) can use a
+/// bundle to update all of them together.  All new files are first staged next to
+/// their targets and only then moved into place.  If any of the renames fails, all
+/// files that were already replaced are restored.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// self_replace::Bundle::new()
+///     .executable("/tmp/update/tool")
+///     .file("/opt/tool/plugin.so", "/tmp/update/plugin.so")
+///     .commit()?;
+/// # Ok(()) }
+/// ```
+///
+/// The current executable can be part of the bundle, in which case it's handled
+/// like [`self_replace`](crate::self_replace) would.
+#[derive(Debug, Clone, Default)]
+pub struct Bundle {
+    files: Vec<(BundleTarget, PathBuf)>,
+}
+
+#[derive(Debug, Clone)]
+enum BundleTarget {
+    Executable,
+    Path(PathBuf),
+}
+
+impl Bundle {
+    /// Creates an empty bundle.
+    pub fn new() -> Bundle {
+        Bundle::default()
+    }
+
+    /// Adds a file to be replaced by the contents of `source`.
+    pub fn file<T: AsRef<Path>, S: AsRef<Path>>(mut self, target: T, source: S) -> Bundle {
+        self.files.push((
+            BundleTarget::Path(target.as_ref().to_path_buf()),
+            source.as_ref().to_path_buf(),
+        ));
+        self
+    }
+
+    /// Adds the current executable to be replaced by `source`.
+    pub fn executable<S: AsRef<Path>>(mut self, source: S) -> Bundle {
+        self.files
+            .push((BundleTarget::Executable, source.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Stages all files and then moves them into place.
+    ///
+    /// The source files are copied, so they can be deleted afterwards.  Targets are
+    /// allowed to not exist yet in which case they are created.  For existing
+    /// targets the permissions are retained.
+    pub fn commit(&self) -> Result<(), io::Error> {
+        let exe = current_exe()?;
+        let mut staged = Vec::new();
+        for (target, source) in &self.files {
+            let target = match *target {
+                BundleTarget::Executable => exe.clone(),
+                BundleTarget::Path(ref path) => path.clone(),
+            };
+            match stage(&target, source) {
+                Ok(path) => staged.push((target, path)),
+                Err(err) => {
+                    remove_staged(&staged);
+                    return Err(err);
+                }
+            }
+        }
+
+        let exe_target = staged
+            .iter()
+            .find(|(target, _)| target.canonicalize().ok().as_ref() == Some(&exe))
+            .map(|(target, _)| target.clone());
+
+        // move the originals aside and the new files into place.  Once this
+        // went through for all files, the transaction is committed.
+        let mut committed: Vec<(PathBuf, Option<PathBuf>)> = Vec::new();
+        for (idx, (target, path)) in staged.iter().enumerate() {
+            if let Err(err) = commit_file(target, path, &mut committed) {
+                for (target, backup) in committed.into_iter().rev() {
+                    match backup {
+                        Some(backup) => fs::rename(&backup, &target).ok(),
+                        None => fs::remove_file(&target).ok(),
+                    };
+                }
+                remove_staged(&staged[idx..]);
+                return Err(err);
+            }
+        }
+
+        // at this point the update went through, so failing to clean up the
+        // old files is not reported.
+        for (target, backup) in committed {
+            if let Some(backup) = backup {
+                remove_backup(&backup, Some(&target) == exe_target.as_ref());
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes all files into a new version folder and then flips a symlink to it.
+    ///
+    /// This is the alternative for versioned installation layouts where the
+    /// application is reached through a symlink like `current -> versions/1.0`.
+    /// All targets must be relative paths, they are created within `version_dir`
+    /// which must not exist yet.  Once all files are written, `link` is atomically
+    /// pointed to `version_dir`.  If `version_dir` is within the folder of `link`
+    /// the link is relative, so that the installation can be moved.  If anything fails, the version folder is removed
+    /// and the link stays untouched.  Adding the current executable to such a
+    /// bundle is not supported.
+    ///
+    /// This is only supported on Unix.
+    pub fn commit_versioned<V: AsRef<Path>, L: AsRef<Path>>(
+        &self,
+        version_dir: V,
+        link: L,
+    ) -> Result<(), io::Error> {
+        let version_dir = version_dir.as_ref();
+        fs::create_dir_all(version_dir.parent().unwrap_or_else(|| Path::new(".")))?;
+        fs::create_dir(version_dir)?;
+        let rv = (|| {
+            for (target, source) in &self.files {
+                let target = match *target {
+                    BundleTarget::Path(ref path) if path.is_relative() => version_dir.join(path),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "versioned bundles require relative targets",
+                        ))
+                    }
+                };
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(source, &target)?;
+            }
+            let link = link.as_ref();
+            crate::fsutil::replace_symlink(link, &link_target(link, version_dir)?)
+        })();
+        if rv.is_err() {
+            fs::remove_dir_all(version_dir).ok();
+        }
+        rv
+    }
+}
+
+/// Returns what a symlink at `link` needs to point to in order to reach `dir`.
+fn link_target(link: &Path, dir: &Path) -> Result<PathBuf, io::Error> {
+    let dir = dir.canonicalize()?;
+    let parent = match link.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => env::current_dir()?.canonicalize()?,
+    };
+    Ok(match dir.strip_prefix(&parent) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => dir,
+    })
+}
+
+fn stage(target: &Path, source: &Path) -> Result<PathBuf, io::Error> {
+    let path = sibling_temp_path(target, "__staged__")?;
+    let rv = (|| {
+        fs::copy(source, &path)?;
+        if let Ok(md) = fs::metadata(target) {
+            fs::set_permissions(&path, md.permissions())?;
+        }
+        Ok(())
+    })();
+    match rv {
+        Ok(()) => Ok(path),
+        Err(err) => {
+            fs::remove_file(&path).ok();
+            Err(err)
+        }
+    }
+}
+
+fn commit_file(
+    target: &Path,
+    staged: &Path,
+    committed: &mut Vec<(PathBuf, Option<PathBuf>)>,
+) -> Result<(), io::Error> {
+    let backup = if fs::symlink_metadata(target).is_ok() {
+        let backup = sibling_temp_path(target, "__backup__")?;
+        fs::rename(target, &backup)?;
+        Some(backup)
+    } else {
+        None
+    };
+    if let Err(err) = fs::rename(staged, target) {
+        if let Some(backup) = backup {
+            fs::rename(&backup, target).ok();
+        }
+        return Err(err);
+    }
+    committed.push((target.to_path_buf(), backup));
+    Ok(())
+}
+
+fn remove_staged(staged: &[(PathBuf, PathBuf)]) {
+    for (_, path) in staged {
+        fs::remove_file(path).ok();
+    }
+}
+
+fn remove_backup(backup: &Path, is_exe: bool) {
+    if is_exe {
+        // on windows the running executable cannot be deleted, so it needs the
+        // same treatment as a self deletion.
+        crate::self_delete_at(backup).ok();
+    } else {
+        fs::remove_file(backup).ok();
+    }
+}
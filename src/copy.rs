@@ -0,0 +1,186 @@
+use std::ffi::CString;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use crate::hash::sha256_reader_with_progress;
+use crate::options::StagingStrategy;
+use crate::progress::{Monitor, ProgressPhase};
+
+/// How much space needs to be left on the file system after staging.
+const FREE_SPACE_MARGIN: u64 = 1024 * 1024;
+
+/// Fails with `ENOSPC` if `dir` does not have room for a file of `size` bytes.
+///
+/// This is checked upfront so that a full disk does not leave a truncated
+/// executable behind which is then attempted to be committed.
+#[allow(clippy::unnecessary_cast)]
+pub fn check_free_space(dir: &Path, size: u64) -> Result<(), io::Error> {
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let available = (stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64);
+    if available < size.saturating_add(FREE_SPACE_MARGIN) {
+        return Err(io::Error::from_raw_os_error(libc::ENOSPC));
+    }
+    Ok(())
+}
+
+/// Ensures that the staged file matches the source.
+///
+/// The size is always compared, the contents only if `hash` is set.
+pub fn verify_contents(
+    source: &Path,
+    staged: &mut fs::File,
+    hash: bool,
+    monitor: &Monitor,
+) -> Result<(), io::Error> {
+    let mismatch = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "staged executable does not match the new executable",
+        )
+    };
+    let size = fs::metadata(source)?.len();
+    if size != staged.metadata()?.len() {
+        return Err(mismatch());
+    }
+    if hash {
+        let total = size * 2;
+        monitor.report(ProgressPhase::Verifying, 0, total)?;
+        staged.seek(SeekFrom::Start(0))?;
+        let staged_hash = sha256_reader_with_progress(&mut *staged, |done| {
+            monitor.report(ProgressPhase::Verifying, done, total)
+        })?;
+        let source_hash = sha256_reader_with_progress(fs::File::open(source)?, |done| {
+            monitor.report(ProgressPhase::Verifying, size + done, total)
+        })?;
+        if staged_hash != source_hash {
+            return Err(mismatch());
+        }
+    }
+    Ok(())
+}
+
+/// Copies the contents of `source` into `target` as cheaply as possible.
+///
+/// On Linux the file is first reflinked (`FICLONE`) which shares the data blocks
+/// on file systems like btrfs or XFS, then copied in the kernel with
+/// `copy_file_range`.  Everywhere else, or if neither is supported, the contents
+/// are copied through user space.
+///
+/// Anything that was in `target` before is discarded, so a failed copy can be
+/// retried.
+pub fn copy_contents(
+    source: &Path,
+    target: &mut fs::File,
+    monitor: &Monitor,
+) -> Result<StagingStrategy, io::Error> {
+    let mut source = fs::File::open(source)?;
+    let total = source.metadata()?.len();
+    target.set_len(0)?;
+    target.seek(SeekFrom::Start(0))?;
+    monitor.report(ProgressPhase::Staging, 0, total)?;
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(strategy) = linux::copy_in_kernel(&source, target, total, monitor)? {
+            return Ok(strategy);
+        }
+    }
+
+    let mut buf = vec![0; 64 * 1024];
+    let mut done = 0;
+    loop {
+        let n = match source.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        target.write_all(&buf[..n])?;
+        done += n as u64;
+        monitor.report(ProgressPhase::Staging, done, total)?;
+    }
+    Ok(StagingStrategy::Copy)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::fs;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    use crate::options::StagingStrategy;
+    use crate::progress::{Monitor, ProgressPhase};
+
+    pub fn copy_in_kernel(
+        source: &fs::File,
+        target: &fs::File,
+        total: u64,
+        monitor: &Monitor,
+    ) -> Result<Option<StagingStrategy>, io::Error> {
+        if unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE as _, source.as_raw_fd()) } == 0 {
+            monitor.report(ProgressPhase::Staging, total, total)?;
+            return Ok(Some(StagingStrategy::Reflink));
+        }
+
+        let mut copied_any = false;
+        let mut done = 0;
+        loop {
+            let rv = unsafe {
+                libc::copy_file_range(
+                    source.as_raw_fd(),
+                    ptr::null_mut(),
+                    target.as_raw_fd(),
+                    ptr::null_mut(),
+                    8 * 1024 * 1024,
+                    0,
+                )
+            };
+            if rv == 0 {
+                break;
+            } else if rv < 0 {
+                let err = io::Error::last_os_error();
+                // not supported for these files, fall back to a regular copy if
+                // nothing was copied so far.
+                if !copied_any
+                    && matches!(
+                        err.raw_os_error(),
+                        Some(libc::ENOSYS)
+                            | Some(libc::EXDEV)
+                            | Some(libc::EINVAL)
+                            | Some(libc::EOPNOTSUPP)
+                            | Some(libc::EPERM)
+                    )
+                {
+                    return Ok(None);
+                }
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            } else {
+                copied_any = true;
+                done += rv as u64;
+                monitor.report(ProgressPhase::Staging, done, total)?;
+            }
+        }
+        Ok(Some(StagingStrategy::CopyFileRange))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_free_space() {
+        let dir = tempfile::tempdir().unwrap();
+        check_free_space(dir.path(), 0).unwrap();
+        let err = check_free_space(dir.path(), u64::MAX).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOSPC));
+    }
+}
@@ -0,0 +1,256 @@
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Component, Path};
+
+use crate::options::{ReplaceOptions, StagingStrategy};
+use crate::retry::{retry, RetryPolicy};
+
+/// A replacement staged relative to an opened directory.
+///
+/// The directory of the executable is opened once without following symlinks and
+/// all further operations are performed relative to it, so a concurrently swapped
+/// symlink anywhere in the path cannot redirect them.  The inode of the executable
+/// is checked when staging and again right before the commit.
+pub struct StagedReplace {
+    dir: fs::File,
+    name: CString,
+    id: (u64, u64),
+    file: fs::File,
+    temp_name: CString,
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    anonymous: bool,
+}
+
+impl StagedReplace {
+    /// Copies `new_executable` into the directory of `exe`.
+    ///
+    /// `exe` must be canonical and `md` the metadata it resolved to.
+    pub fn prepare(
+        exe: &Path,
+        new_executable: &Path,
+        md: &fs::Metadata,
+        options: &ReplaceOptions,
+        retries: &mut u32,
+    ) -> Result<(StagedReplace, StagingStrategy), io::Error> {
+        let parent = exe.parent().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "executable has no parent")
+        })?;
+        let name = exe
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+        let dir = open_dir_nofollow(parent)?;
+        debug!(dir = %parent.display(), "opened directory without following symlinks");
+        let name = CString::new(name.as_bytes())?;
+        let id = (md.dev(), md.ino());
+        check_id(&dir, &name, id)?;
+        let len = fs::metadata(new_executable)?.len();
+        crate::copy::check_free_space(parent, len)?;
+
+        let temp_name = CString::new(
+            crate::fsutil::sibling_temp_path(exe, "__temp__")?
+                .file_name()
+                .unwrap()
+                .as_bytes(),
+        )?;
+        #[cfg(target_os = "linux")]
+        let anonymous =
+            open_anonymous(dir.as_raw_fd(), CStr::from_bytes_with_nul(b".\0").unwrap())?;
+        #[cfg(not(target_os = "linux"))]
+        let anonymous = None;
+        let (mut file, anonymous) = match anonymous {
+            Some(file) => (file, true),
+            None => {
+                let fd = unsafe {
+                    libc::openat(
+                        dir.as_raw_fd(),
+                        temp_name.as_ptr(),
+                        libc::O_RDWR
+                            | libc::O_CREAT
+                            | libc::O_EXCL
+                            | libc::O_NOFOLLOW
+                            | libc::O_CLOEXEC,
+                        0o600_u32,
+                    )
+                };
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                (unsafe { fs::File::from_raw_fd(fd) }, false)
+            }
+        };
+
+        let rv = (|| {
+            let monitor = options.monitor();
+            let strategy = retry(options.retry.as_ref(), retries, || {
+                crate::copy::copy_contents(new_executable, &mut file, &monitor)
+            })?;
+            debug!(
+                staging = ?strategy,
+                anonymous,
+                bytes = len,
+                "copied the new executable"
+            );
+            file.set_permissions(fs::Permissions::from_mode(md.mode() & 0o7777))?;
+            crate::copy::verify_contents(new_executable, &mut file, options.verify, &monitor)?;
+            Ok(strategy)
+        })();
+        let staged = StagedReplace {
+            dir,
+            name,
+            id,
+            file,
+            temp_name,
+            anonymous,
+        };
+        match rv {
+            Ok(strategy) => Ok((staged, strategy)),
+            Err(err) => {
+                staged.discard();
+                Err(err)
+            }
+        }
+    }
+
+    /// Moves the staged executable over the old one.
+    pub fn commit(self, policy: Option<&RetryPolicy>, retries: &mut u32) -> Result<(), io::Error> {
+        let rv = (|| {
+            check_id(&self.dir, &self.name, self.id)?;
+            #[cfg(target_os = "linux")]
+            {
+                if self.anonymous {
+                    link_anonymous(&self.file, self.dir.as_raw_fd(), &self.temp_name)?;
+                }
+            }
+            debug!(name = ?self.name, "renaming into place");
+            retry(policy, retries, || {
+                cvt(unsafe {
+                    libc::renameat(
+                        self.dir.as_raw_fd(),
+                        self.temp_name.as_ptr(),
+                        self.dir.as_raw_fd(),
+                        self.name.as_ptr(),
+                    )
+                })
+            })
+        })();
+        if rv.is_err() {
+            self.discard();
+        }
+        rv
+    }
+
+    /// Removes the staged executable.
+    pub fn discard(&self) {
+        debug!("removing the staged executable");
+        unsafe { libc::unlinkat(self.dir.as_raw_fd(), self.temp_name.as_ptr(), 0) };
+    }
+}
+
+/// Opens a directory by walking the path without following any symlinks.
+fn open_dir_nofollow(path: &Path) -> Result<fs::File, io::Error> {
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let mut dir = open_at(
+        libc::AT_FDCWD,
+        CStr::from_bytes_with_nul(b"/\0").unwrap(),
+        flags,
+    )?;
+    for component in path.components() {
+        match component {
+            Component::RootDir => {}
+            Component::Normal(name) => {
+                let name = CString::new(name.as_bytes())?;
+                dir = open_at(dir.as_raw_fd(), &name, flags)?;
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "executable path must be canonical",
+                ))
+            }
+        }
+    }
+    Ok(dir)
+}
+
+fn open_at(dirfd: i32, name: &CStr, flags: i32) -> Result<fs::File, io::Error> {
+    let fd = unsafe { libc::openat(dirfd, name.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { fs::File::from_raw_fd(fd) })
+}
+
+/// Ensures that `name` in `dir` still is the file that was resolved.
+#[allow(clippy::unnecessary_cast)]
+fn check_id(dir: &fs::File, name: &CStr, id: (u64, u64)) -> Result<(), io::Error> {
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    cvt(unsafe {
+        libc::fstatat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            &mut st,
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    })?;
+    if (st.st_dev as u64, st.st_ino as u64) != id {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "executable was swapped out during the replacement",
+        ));
+    }
+    Ok(())
+}
+
+/// Opens an unnamed `O_TMPFILE` inode in the directory `path` relative to `dirfd`.
+///
+/// Returns `None` if the kernel or the file system does not support this.  The
+/// file can only be linked into place through `/proc`, so it's also not used if
+/// that is not mounted.
+#[cfg(target_os = "linux")]
+pub fn open_anonymous(dirfd: i32, path: &CStr) -> Result<Option<fs::File>, io::Error> {
+    if !Path::new("/proc/self/fd").is_dir() {
+        return Ok(None);
+    }
+    let fd = unsafe {
+        libc::openat(
+            dirfd,
+            path.as_ptr(),
+            libc::O_TMPFILE | libc::O_RDWR | libc::O_CLOEXEC,
+            0o600_u32,
+        )
+    };
+    if fd < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::EOPNOTSUPP) | Some(libc::EISDIR) | Some(libc::EINVAL) => Ok(None),
+            _ => Err(err),
+        };
+    }
+    Ok(Some(unsafe { fs::File::from_raw_fd(fd) }))
+}
+
+/// Gives a file opened with [`open_anonymous`] the name `name` relative to `dirfd`.
+#[cfg(target_os = "linux")]
+pub fn link_anonymous(file: &fs::File, dirfd: i32, name: &CStr) -> Result<(), io::Error> {
+    let source = CString::new(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+    cvt(unsafe {
+        libc::linkat(
+            libc::AT_FDCWD,
+            source.as_ptr(),
+            dirfd,
+            name.as_ptr(),
+            libc::AT_SYMLINK_FOLLOW,
+        )
+    })
+}
+
+fn cvt(rv: i32) -> Result<(), io::Error> {
+    if rv != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+use std::io;
+
+/// A serializable description of an [`io::Error`].
+///
+/// Errors that happen after an operation was committed do not fail it.  They are
+/// reported in the outcome instead, for instance in
+/// [`ReplaceOutcome::prune_error`](crate::ReplaceOutcome::prune_error).  Unlike
+/// `io::Error` this can be cloned, compared and (with the `serde` feature)
+/// serialized along with the outcome.
+///
+/// ```
+/// use self_replace::ErrorReport;
+/// let err = std::io::Error::from(std::io::ErrorKind::NotFound);
+/// let report = ErrorReport::from(&err);
+/// assert_eq!(report.kind, "NotFound");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct ErrorReport {
+    /// The [`io::ErrorKind`] as formatted with `Debug` (like `NotFound`).
+    pub kind: String,
+    /// The raw operating system error code, if the error came from the system.
+    pub os_error: Option<i32>,
+    /// The error message.
+    pub message: String,
+}
+
+impl From<&io::Error> for ErrorReport {
+    fn from(err: &io::Error) -> ErrorReport {
+        ErrorReport {
+            kind: format!("{:?}", err.kind()),
+            os_error: err.raw_os_error(),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
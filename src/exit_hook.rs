@@ -0,0 +1,165 @@
+use std::ffi::CString;
+use std::ffi::OsStr;
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Once;
+
+/// The paths to unlink on shutdown.  These are stored as raw pointer to a list
+/// of C strings so that they can be picked up from a signal handler without
+/// allocating or locking.
+static PENDING_DELETE: AtomicPtr<Vec<CString>> = AtomicPtr::new(ptr::null_mut());
+
+/// A file to move over another one on shutdown, stored like the deletions.
+static PENDING_RENAME: AtomicPtr<(CString, CString)> = AtomicPtr::new(ptr::null_mut());
+
+static INSTALL_HOOKS: Once = Once::new();
+static mut PREVIOUS_SIGTERM: mem::MaybeUninit<libc::sigaction> = mem::MaybeUninit::uninit();
+static mut PREVIOUS_SIGINT: mem::MaybeUninit<libc::sigaction> = mem::MaybeUninit::uninit();
+
+/// Records that the given paths should be unlinked when the process shuts down.
+pub fn schedule_delete(paths: &[PathBuf]) -> Result<(), io::Error> {
+    let paths = paths
+        .iter()
+        .map(|x| CString::new(x.as_os_str().as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    install_hooks()?;
+    let old = PENDING_DELETE.swap(Box::into_raw(Box::new(paths)), Ordering::SeqCst);
+    if !old.is_null() {
+        drop(unsafe { Box::from_raw(old) });
+    }
+    Ok(())
+}
+
+/// Revokes a pending deletion.  Returns `true` if there was one.
+pub fn cancel_delete() -> bool {
+    let old = PENDING_DELETE.swap(ptr::null_mut(), Ordering::SeqCst);
+    if old.is_null() {
+        return false;
+    }
+    drop(unsafe { Box::from_raw(old) });
+    true
+}
+
+/// Records that `from` should be renamed to `to` when the process shuts down.
+///
+/// Returns the source of a previously scheduled rename which is replaced.
+pub fn schedule_rename(from: &Path, to: &Path) -> Result<Option<PathBuf>, io::Error> {
+    let pair = (
+        CString::new(from.as_os_str().as_bytes())?,
+        CString::new(to.as_os_str().as_bytes())?,
+    );
+    install_hooks()?;
+    let old = PENDING_RENAME.swap(Box::into_raw(Box::new(pair)), Ordering::SeqCst);
+    Ok(take_rename_source(old))
+}
+
+/// Revokes a pending rename.  Returns the source of the rename if there was one.
+pub fn cancel_rename() -> Option<PathBuf> {
+    take_rename_source(PENDING_RENAME.swap(ptr::null_mut(), Ordering::SeqCst))
+}
+
+/// Returns `true` if a rename is pending.
+pub fn has_pending_rename() -> bool {
+    !PENDING_RENAME.load(Ordering::SeqCst).is_null()
+}
+
+fn take_rename_source(pair: *mut (CString, CString)) -> Option<PathBuf> {
+    if pair.is_null() {
+        return None;
+    }
+    let (from, _) = *unsafe { Box::from_raw(pair) };
+    Some(PathBuf::from(OsStr::from_bytes(from.as_bytes())))
+}
+
+fn install_hooks() -> Result<(), io::Error> {
+    let mut rv = Ok(());
+    INSTALL_HOOKS.call_once(|| unsafe {
+        if libc::atexit(run_at_exit) != 0 {
+            rv = Err(io::Error::new(
+                io::ErrorKind::Other,
+                "could not register exit handler",
+            ));
+            return;
+        }
+
+        // failing to install the signal handlers is not fatal, the handling of
+        // signals is only best effort anyways.
+        install_signal_hook(libc::SIGTERM, ptr::addr_of_mut!(PREVIOUS_SIGTERM).cast());
+        install_signal_hook(libc::SIGINT, ptr::addr_of_mut!(PREVIOUS_SIGINT).cast());
+    });
+    rv
+}
+
+/// Installs [`on_signal`] for `sig` and stores the previous disposition.
+///
+/// Ignored signals stay ignored as they never terminate the process.  The flags
+/// of the previous disposition are kept so that chained handlers behave as before
+/// (for instance with regards to `SA_RESTART`).
+unsafe fn install_signal_hook(sig: c_int, previous: *mut libc::sigaction) {
+    if libc::sigaction(sig, ptr::null(), previous) != 0 || (*previous).sa_sigaction == libc::SIG_IGN
+    {
+        return;
+    }
+    let mut action: libc::sigaction = mem::zeroed();
+    action.sa_sigaction = on_signal as *const () as usize;
+    action.sa_flags = ((*previous).sa_flags | libc::SA_SIGINFO) & !libc::SA_RESETHAND;
+    action.sa_mask = (*previous).sa_mask;
+    libc::sigaction(sig, &action, ptr::null_mut());
+}
+
+/// Performs the pending operations.  This must stay async-signal-safe.
+fn run_pending() {
+    // the lists are intentionally leaked as freeing is not allowed in a signal handler.
+    let pair = PENDING_RENAME.swap(ptr::null_mut(), Ordering::SeqCst);
+    if !pair.is_null() {
+        let (ref from, ref to) = *unsafe { &*pair };
+        unsafe { libc::rename(from.as_ptr(), to.as_ptr()) };
+    }
+    let paths = PENDING_DELETE.swap(ptr::null_mut(), Ordering::SeqCst);
+    if !paths.is_null() {
+        for path in unsafe { &*paths } {
+            unsafe { libc::unlink(path.as_ptr()) };
+        }
+    }
+}
+
+extern "C" fn run_at_exit() {
+    run_pending();
+}
+
+extern "C" fn on_signal(sig: c_int, info: *mut libc::siginfo_t, ctx: *mut c_void) {
+    // hand over to whatever was installed before us.  If that was the default
+    // disposition the process is about to terminate, so the pending operations
+    // are performed before restoring it and terminating the way we would have
+    // without us.  A custom handler might keep the process alive, in which case
+    // the operations stay pending (and can still be cancelled) until it exits.
+    unsafe {
+        let previous = if sig == libc::SIGINT {
+            &*(ptr::addr_of!(PREVIOUS_SIGINT) as *const libc::sigaction)
+        } else {
+            &*(ptr::addr_of!(PREVIOUS_SIGTERM) as *const libc::sigaction)
+        };
+        match previous.sa_sigaction {
+            libc::SIG_IGN => {}
+            libc::SIG_DFL => {
+                run_pending();
+                libc::signal(sig, libc::SIG_DFL);
+                libc::raise(sig);
+            }
+            handler if previous.sa_flags & libc::SA_SIGINFO != 0 => {
+                let handler: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) =
+                    mem::transmute(handler);
+                handler(sig, info, ctx);
+            }
+            handler => {
+                let handler: extern "C" fn(c_int) = mem::transmute(handler);
+                handler(sig);
+            }
+        }
+    }
+}
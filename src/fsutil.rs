@@ -0,0 +1,89 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Returns the path of the current executable with all symlinks resolved.
+pub fn current_exe() -> Result<PathBuf, io::Error> {
+    env::current_exe()?.canonicalize()
+}
+
+/// Returns the hidden path `.{name}.{tag}` next to the given path.
+///
+/// This is where state that belongs to a file (like the backups or the health
+/// state of the executable) is kept.
+pub fn sibling_path(path: &Path, tag: &str) -> Result<PathBuf, io::Error> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut name = String::from(".");
+    name.push_str(&file_name.to_string_lossy());
+    name.push('.');
+    name.push_str(tag);
+    Ok(path.with_file_name(name))
+}
+
+/// Picks a random, hidden name next to the given path.
+///
+/// Staying in the same directory guarantees that a rename between the two does
+/// not have to cross file systems.  The name looks like `.{name}.{tag}{random}`.
+pub fn sibling_temp_path(path: &Path, tag: &str) -> Result<PathBuf, io::Error> {
+    let mut rng = fastrand::Rng::new();
+    let mut tag = tag.to_string();
+    for _ in 0..16 {
+        tag.push(rng.lowercase());
+    }
+    sibling_path(path, &tag)
+}
+
+/// Replaces the contents of `path` atomically.
+///
+/// The contents are written to a temporary file next to `path` which is then
+/// renamed over it, so readers see either the old or the new contents.
+pub fn write_atomic(path: &Path, contents: &str) -> Result<(), io::Error> {
+    let tmp = sibling_temp_path(path, "__temp__")?;
+    if let Err(err) = fs::write(&tmp, contents).and_then(|_| fs::rename(&tmp, path)) {
+        fs::remove_file(&tmp).ok();
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Overwrites the contents of `target` with the contents of `source`.
+///
+/// This is the fallback for when a file cannot be renamed over, for instance
+/// because it's a bind mount.  Unlike a rename this is not atomic, and it fails
+/// (with `ETXTBSY` on Unix) if `target` is a running executable.  The file keeps
+/// its identity and permissions.
+pub fn write_in_place(source: &Path, target: &Path) -> Result<(), io::Error> {
+    let mut source = fs::File::open(source)?;
+    let mut target = fs::OpenOptions::new().write(true).open(target)?;
+    target.set_len(0)?;
+    io::copy(&mut source, &mut target)?;
+    target.sync_all()
+}
+
+/// Atomically points the symlink at `link` to `target`.
+///
+/// A new symlink is created under a temporary name and renamed over the old one,
+/// so `link` is never missing.  This is only supported on Unix.
+pub fn replace_symlink(link: &Path, target: &Path) -> Result<(), io::Error> {
+    #[cfg(unix)]
+    {
+        let tmp = sibling_temp_path(link, "__temp__")?;
+        std::os::unix::fs::symlink(target, &tmp)?;
+        if let Err(err) = fs::rename(&tmp, link) {
+            fs::remove_file(&tmp).ok();
+            return Err(err);
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (link, target);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "symlink based layouts are only supported on unix",
+        ))
+    }
+}
@@ -0,0 +1,52 @@
+use std::fmt::Write;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+/// Returns the hex encoded SHA-256 digest of the file at the given path.
+pub fn sha256_file(path: &Path) -> Result<String, io::Error> {
+    sha256_reader(fs::File::open(path)?)
+}
+
+/// Returns the hex encoded SHA-256 digest of everything read from `reader`.
+pub fn sha256_reader<R: Read>(reader: R) -> Result<String, io::Error> {
+    sha256_reader_with_progress(reader, |_| Ok(()))
+}
+
+/// Like [`sha256_reader`] but calls `progress` with the number of bytes hashed
+/// so far after every chunk.  An error returned from it aborts the hashing.
+pub fn sha256_reader_with_progress<R, F>(
+    mut reader: R,
+    mut progress: F,
+) -> Result<String, io::Error>
+where
+    R: Read,
+    F: FnMut(u64) -> Result<(), io::Error>,
+{
+    let mut hasher = Sha256::new();
+    let mut done = 0;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                hasher.update(&buf[..n]);
+                done += n as u64;
+                progress(done)?;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut rv = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(rv, "{byte:02x}").unwrap();
+    }
+    rv
+}
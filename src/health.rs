@@ -0,0 +1,245 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "audit")]
+use crate::audit::{AuditLog, AuditOperation};
+use crate::fsutil::{current_exe, sibling_path, write_atomic};
+
+/// The result of [`check_health`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "status", rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum HealthStatus {
+    /// There is no unconfirmed update.
+    Healthy,
+    /// The executable was updated and has not called [`mark_healthy`] yet.
+    Unconfirmed {
+        /// How often the new version was launched so far, including this launch.
+        launches: u32,
+        /// After how many launches the update is rolled back.
+        max_launches: u32,
+    },
+    /// The update was not confirmed in time and the backup was restored.
+    ///
+    /// The running process is still the failed version.  It should exit (or
+    /// re-execute itself) as soon as possible.
+    RolledBack {
+        /// The backup that was restored.
+        backup: PathBuf,
+    },
+}
+
+/// Counts a launch of an updated executable and rolls back failed updates.
+///
+/// When an update is installed with [`ReplaceOptions::health_check`], the new
+/// version needs to confirm that it works by calling [`mark_healthy`] within the
+/// given number of launches.  This function is meant to be called early in `main`
+/// and does the counting.  Once the new version was launched more often than
+/// allowed without confirming, the backup of the previous version is restored and
+/// [`HealthStatus::RolledBack`] is returned.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// use self_replace::HealthStatus;
+/// if let HealthStatus::RolledBack { .. } = self_replace::check_health()? {
+///     eprintln!("update failed, restored the previous version");
+///     std::process::exit(1);
+/// }
+/// // once the application is known to work:
+/// self_replace::mark_healthy()?;
+/// # Ok(()) }
+/// ```
+///
+/// The state is stored in a hidden file `.{name}.health` next to the executable.
+///
+/// [`ReplaceOptions::health_check`]: crate::ReplaceOptions::health_check
+pub fn check_health() -> Result<HealthStatus, io::Error> {
+    let exe = current_exe()?;
+    let path = state_path(&exe)?;
+    let mut state = match HealthState::load(&path)? {
+        Some(state) => state,
+        None => return Ok(HealthStatus::Healthy),
+    };
+
+    state.launches += 1;
+    if state.launches <= state.max_launches {
+        state.save(&path)?;
+        return Ok(HealthStatus::Unconfirmed {
+            launches: state.launches,
+            max_launches: state.max_launches,
+        });
+    }
+
+    let backup = crate::backup::backups_dir(&exe)?.join(&state.backup);
+    // the rollback must not depend on the audit log, so it's only recorded if
+    // the log can still be opened.
+    #[cfg(feature = "audit")]
+    let audit = match state.audit_log {
+        Some(ref log) => {
+            let version = crate::backup::read_backups(&crate::backup::backups_dir(&exe)?)
+                .ok()
+                .and_then(|backups| backups.into_iter().find(|x| x.path == backup))
+                .and_then(|x| x.version);
+            AuditLog::open(
+                Path::new(log),
+                AuditOperation::Rollback,
+                &exe,
+                Some(&backup),
+            )
+            .ok()
+            .map(|audit| audit.versions(None, version.as_deref()))
+        }
+        None => None,
+    };
+    let rv = crate::self_replace(&backup);
+    #[cfg(feature = "audit")]
+    {
+        if let Some(audit) = audit {
+            audit.finish(&rv);
+        }
+    }
+    rv?;
+    fs::remove_file(&path)?;
+    Ok(HealthStatus::RolledBack { backup })
+}
+
+/// Confirms that an updated executable works.
+///
+/// This stops the launch counting of [`check_health`].  The backup of the
+/// previous version is kept.  If there is no unconfirmed update, this does nothing.
+pub fn mark_healthy() -> Result<(), io::Error> {
+    match fs::remove_file(state_path(&current_exe()?)?) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Records that the executable is about to be updated and needs to confirm its health.
+///
+/// This happens before the update is committed, so that committing is the last
+/// step that can fail.  If it fails, the returned guard has to be reverted.  A
+/// rollback is recorded in the audit log if one is given.
+pub(crate) fn arm(
+    exe: &Path,
+    backup: &Path,
+    max_launches: u32,
+    audit_log: Option<&Path>,
+) -> Result<Armed, io::Error> {
+    // backups are always in the backups folder and have ascii names, so only the
+    // file name is recorded.
+    let backup = backup
+        .file_name()
+        .and_then(|x| x.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid backup path"))?;
+    let path = state_path(exe)?;
+    let previous = match fs::read_to_string(&path) {
+        Ok(previous) => Some(previous),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    HealthState {
+        backup: backup.to_string(),
+        launches: 0,
+        max_launches,
+        audit_log: match audit_log {
+            Some(log) => Some(
+                log.to_str()
+                    .filter(|x| !x.contains('\n'))
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidInput, "invalid audit log path")
+                    })?
+                    .to_string(),
+            ),
+            None => None,
+        },
+    }
+    .save(&path)?;
+    Ok(Armed { path, previous })
+}
+
+/// The health state written by [`arm`] for an update that is not committed yet.
+pub(crate) struct Armed {
+    path: PathBuf,
+    previous: Option<String>,
+}
+
+impl Armed {
+    /// Restores the state from before the update.
+    pub fn revert(self) {
+        match self.previous {
+            Some(previous) => write_atomic(&self.path, &previous).ok(),
+            None => fs::remove_file(&self.path).ok(),
+        };
+    }
+}
+
+/// Returns the backup an unconfirmed update would roll back to.
+pub(crate) fn pending_backup(exe: &Path) -> Result<Option<PathBuf>, io::Error> {
+    Ok(match HealthState::load(&state_path(exe)?)? {
+        Some(state) => Some(crate::backup::backups_dir(exe)?.join(state.backup)),
+        None => None,
+    })
+}
+
+struct HealthState {
+    backup: String,
+    launches: u32,
+    max_launches: u32,
+    audit_log: Option<String>,
+}
+
+impl HealthState {
+    fn load(path: &Path) -> Result<Option<HealthState>, io::Error> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut backup = None;
+        let mut launches = None;
+        let mut max_launches = None;
+        let mut audit_log = None;
+        for line in contents.lines() {
+            let (key, value) = match line.find(' ') {
+                Some(idx) => (&line[..idx], &line[idx + 1..]),
+                None => (line, ""),
+            };
+            match key {
+                "backup" => backup = Some(value.to_string()),
+                "launches" => launches = value.parse().ok(),
+                "max-launches" => max_launches = value.parse().ok(),
+                "audit-log" => audit_log = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        match (backup, launches, max_launches) {
+            (Some(backup), Some(launches), Some(max_launches)) => Ok(Some(HealthState {
+                backup,
+                launches,
+                max_launches,
+                audit_log,
+            })),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid health state in {}", path.display()),
+            )),
+        }
+    }
+
+    fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let mut contents = format!(
+            "backup {}\nlaunches {}\nmax-launches {}\n",
+            self.backup, self.launches, self.max_launches
+        );
+        if let Some(ref audit_log) = self.audit_log {
+            contents.push_str(&format!("audit-log {audit_log}\n"));
+        }
+        write_atomic(path, &contents)
+    }
+}
+
+fn state_path(exe: &Path) -> Result<PathBuf, io::Error> {
+    sibling_path(exe, "health")
+}
@@ -0,0 +1,196 @@
+use std::env;
+use std::env::consts::EXE_SUFFIX;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(unix)]
+use crate::unix as platform;
+#[cfg(windows)]
+use crate::windows as platform;
+
+static HELPER_ARG: &str = "__self_replace_update_helper__";
+
+/// Replaces the executable from a detached helper after the current process exits.
+///
+/// This is useful for applications that cannot replace themselves and continue
+/// running, such as GUI or TUI applications that want to restart into the new
+/// version.  A copy of the current executable is spawned with a magic argument and
+/// waits for the current process to shut down.  Once that happened, the new
+/// executable is moved into place and the application is optionally relaunched.
+///
+/// For this to work, [`handle_update_helper`] must be invoked at the very start
+/// of `main`.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// self_replace::UpdateHelper::new("/path/to/new/binary")
+///     .relaunch(["--updated"])
+///     .spawn()?;
+/// std::process::exit(0);
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone)]
+pub struct UpdateHelper {
+    new_executable: PathBuf,
+    relaunch_args: Option<Vec<OsString>>,
+    in_place: bool,
+}
+
+impl UpdateHelper {
+    /// Creates a helper that will put `new_executable` in place of the current one.
+    pub fn new<P: AsRef<Path>>(new_executable: P) -> UpdateHelper {
+        UpdateHelper {
+            new_executable: new_executable.as_ref().to_path_buf(),
+            relaunch_args: None,
+            in_place: false,
+        }
+    }
+
+    /// Relaunches the updated executable with the given arguments once done.
+    ///
+    /// The relaunched process is started by the detached helper, so it has no
+    /// terminal: on Unix it runs in its own session with stdin, stdout and stderr
+    /// connected to `/dev/null`, on Windows it has no console.  This is meant for
+    /// GUI applications.  Console and TUI applications should not relaunch this
+    /// way.  On Unix they can instead replace themselves with
+    /// [`self_replace`](crate::self_replace) and `exec` the new version, which
+    /// keeps the terminal.
+    pub fn relaunch<I, S>(mut self, args: I) -> UpdateHelper
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.relaunch_args = Some(args.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Overwrites the contents of the executable instead of renaming over it.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn in_place(mut self, yes: bool) -> UpdateHelper {
+        self.in_place = yes;
+        self
+    }
+
+    /// Spawns the helper.
+    ///
+    /// The new executable is copied next to the current one before this function
+    /// returns, so the source binary can be deleted afterwards.  The actual
+    /// replacement only happens once the current process has exited.
+    pub fn spawn(&self) -> Result<(), io::Error> {
+        let exe = crate::fsutil::current_exe()?;
+        let dir = exe.parent().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Other,
+                "executable has no known parent folder",
+            )
+        })?;
+        let hint = exe.file_stem().and_then(|x| x.to_str()).unwrap_or("");
+
+        let staged = keep_temp_file(dir, &format!(".{hint}.__staged__"), "")?;
+        let helper = match keep_temp_file(dir, &format!(".{hint}.__update__"), EXE_SUFFIX) {
+            Ok(helper) => helper,
+            Err(err) => {
+                fs::remove_file(&staged).ok();
+                return Err(err);
+            }
+        };
+
+        let rv = (|| {
+            fs::copy(&self.new_executable, &staged)?;
+            fs::set_permissions(&staged, exe.metadata()?.permissions())?;
+            fs::copy(&exe, &helper)?;
+
+            let mut cmd = Command::new(&helper);
+            cmd.arg(HELPER_ARG)
+                .arg(platform::parent_token()?)
+                .arg(&exe)
+                .arg(&staged)
+                .arg(if self.in_place { "write" } else { "rename" });
+            match self.relaunch_args {
+                Some(ref args) => cmd.arg("1").args(args),
+                None => cmd.arg("0"),
+            };
+            platform::spawn_detached(&mut cmd)
+        })();
+
+        if rv.is_err() {
+            fs::remove_file(&staged).ok();
+            fs::remove_file(&helper).ok();
+        }
+        rv
+    }
+}
+
+/// Runs the update helper logic if this process was spawned as one.
+///
+/// This must be called at the very start of `main` in any executable that uses
+/// [`UpdateHelper`].  If the process was not spawned as an update helper this does
+/// nothing, otherwise it performs the update and exits the process.
+pub fn handle_update_helper() {
+    let mut args = env::args_os().skip(1);
+    if args.next().as_deref() != Some(OsStr::new(HELPER_ARG)) {
+        return;
+    }
+    let rv = run_helper(args);
+
+    // the helper is a copy of the original executable that was only created for
+    // this purpose, so it gets rid of itself no matter if the update worked.
+    crate::self_delete().ok();
+    process::exit(if rv.is_ok() { 0 } else { 1 });
+}
+
+fn run_helper<I: Iterator<Item = OsString>>(mut args: I) -> Result<(), io::Error> {
+    let mut next_arg = || {
+        args.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "malformed update helper call")
+        })
+    };
+    let token = next_arg()?;
+    let target = PathBuf::from(next_arg()?);
+    let staged = PathBuf::from(next_arg()?);
+    let in_place = next_arg()? == "write";
+    let relaunch = next_arg()? == "1";
+    let relaunch_args: Vec<OsString> = args.collect();
+
+    platform::wait_for_parent(&token)?;
+
+    // Even after the parent is gone the file system might not have caught up
+    // yet with releasing the executable (mostly a concern on Windows), so give
+    // the final rename a few tries.
+    let mut attempts = 0;
+    while let Err(err) = if in_place {
+        crate::fsutil::write_in_place(&staged, &target)
+    } else {
+        fs::rename(&staged, &target)
+    } {
+        attempts += 1;
+        if attempts >= 10 {
+            fs::remove_file(&staged).ok();
+            return Err(err);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    if in_place {
+        fs::remove_file(&staged).ok();
+    }
+
+    if relaunch {
+        platform::spawn_detached(Command::new(&target).args(relaunch_args))?;
+    }
+    Ok(())
+}
+
+// Creates an empty, persisted temporary file in the given directory.
+fn keep_temp_file(dir: &Path, prefix: &str, suffix: &str) -> Result<PathBuf, io::Error> {
+    let tmp = tempfile::Builder::new()
+        .prefix(prefix)
+        .suffix(suffix)
+        .tempfile_in(dir)?;
+    let (_, path) = tmp.keep()?;
+    Ok(path)
+}
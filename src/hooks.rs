@@ -0,0 +1,126 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::options::{DeleteOutcome, ReplaceOutcome};
+
+/// The operation a [`Hooks`] implementation is invoked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+    /// [`self_replace_with`](crate::self_replace_with).
+    Replace,
+    /// [`self_delete_with`](crate::self_delete_with).
+    Delete,
+}
+
+/// Describes the operation passed to the methods of [`Hooks`].
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct HookContext<'a> {
+    /// The operation that is running.
+    pub operation: Operation,
+    /// The executable that is replaced or deleted.
+    pub executable: &'a Path,
+    /// The new executable when replacing.
+    pub new_executable: Option<&'a Path>,
+}
+
+/// What an operation did, passed to [`Hooks::post_commit`].
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum HookOutcome<'a> {
+    /// The outcome of a replacement.
+    Replace(&'a ReplaceOutcome),
+    /// The outcome of a deletion.
+    Delete(&'a DeleteOutcome),
+}
+
+/// Runs custom logic at fixed points of a replacement or deletion.
+///
+/// All methods have empty default implementations so only the interesting ones
+/// need to be implemented.  The hooks are invoked in this order:
+///
+/// * [`pre_stage`](Self::pre_stage) before anything is touched.  This is only
+///   invoked when replacing.
+/// * [`pre_commit`](Self::pre_commit) once the new executable is staged and
+///   verified, right before the old executable is replaced (or deleted).
+/// * [`post_commit`](Self::post_commit) once the operation succeeded.
+/// * [`on_error`](Self::on_error) instead of `post_commit` if the operation
+///   failed for any reason, including a veto.
+///
+/// Returning an error from `pre_stage` or `pre_commit` vetoes the operation.  It
+/// then fails with that error and everything staged so far is removed.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// use std::io;
+/// use self_replace::{HookContext, Hooks, ReplaceOptions};
+///
+/// struct StopService;
+///
+/// impl Hooks for StopService {
+///     fn pre_commit(&self, _ctx: &HookContext<'_>) -> Result<(), io::Error> {
+///         // take the service offline, fail to keep the old version
+///         Ok(())
+///     }
+///
+///     fn on_error(&self, _ctx: &HookContext<'_>, err: &io::Error) {
+///         eprintln!("update failed: {err}");
+///     }
+/// }
+///
+/// let options = ReplaceOptions::new().hooks(StopService);
+/// self_replace::self_replace_with("/path/to/new/binary", &options)?;
+/// # Ok(()) }
+/// ```
+///
+/// On Windows staging and committing are a single step, so `pre_stage` and
+/// `pre_commit` are invoked right after each other.
+pub trait Hooks: Send + Sync {
+    /// Invoked before the new executable is staged.
+    fn pre_stage(&self, ctx: &HookContext<'_>) -> Result<(), io::Error> {
+        let _ = ctx;
+        Ok(())
+    }
+
+    /// Invoked right before the change is committed.
+    fn pre_commit(&self, ctx: &HookContext<'_>) -> Result<(), io::Error> {
+        let _ = ctx;
+        Ok(())
+    }
+
+    /// Invoked after the operation succeeded.
+    fn post_commit(&self, ctx: &HookContext<'_>, outcome: HookOutcome<'_>) {
+        let _ = ctx;
+        let _ = outcome;
+    }
+
+    /// Invoked when the operation failed.
+    fn on_error(&self, ctx: &HookContext<'_>, err: &io::Error) {
+        let _ = ctx;
+        let _ = err;
+    }
+}
+
+/// Hooks as stored in the options.
+#[derive(Clone)]
+pub(crate) struct SharedHooks(pub Arc<dyn Hooks>);
+
+impl fmt::Debug for SharedHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Hooks")
+    }
+}
+
+/// Invokes [`Hooks::pre_commit`] if hooks are set.
+pub(crate) fn pre_commit(
+    hooks: Option<&SharedHooks>,
+    ctx: &HookContext<'_>,
+) -> Result<(), io::Error> {
+    match hooks {
+        Some(hooks) => hooks.0.pre_commit(ctx),
+        None => Ok(()),
+    }
+}
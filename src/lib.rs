@@ -35,6 +35,13 @@
 //! # Ok(()) }
 //! ```
 //!
+//! The same can be accomplished in one go with [`self_delete_tree`] which also guards
+//! against accidentally deleting folders that do not contain the executable.
+//!
+//! Applications which place files all over the file system (shell completions, man
+//! pages etc.) can record them in an [`UninstallManifest`] at installation time and
+//! later remove all of them together with the executable with [`self_uninstall`].
+//!
 //! ## Self Replacing
 //!
 //! This replaces the binary with another binary.  The provided path is copied over and
@@ -50,6 +57,73 @@
 //! # Ok(()) }
 //! ```
 //!
+//! If the executable ships with resources that need to match its version, a
+//! [`Bundle`] can be used to replace all of these files as one unit.
+//!
+//! On Unix, a [`VersionedInstall`] can be used instead of replacing the executable
+//! in place.  Every version then lives in its own folder and an atomically replaced
+//! `current` symlink selects the active one, which makes rollbacks trivial.
+//!
+//! Updates can be made safer by keeping a backup of the previous version with
+//! [`ReplaceOptions::backup`].  With [`ReplaceOptions::health_check`] the new
+//! version additionally has to confirm that it works with [`mark_healthy`] within a
+//! number of launches, otherwise [`check_health`] restores the backup.  Backups are
+//! listed with [`list_backups`] and cleaned up according to a [`RetentionPolicy`]
+//! with [`prune_backups`].
+//!
+//! For compliance purposes, replacements, rollbacks and deletions can be recorded
+//! in an append-only log of JSON lines with `ReplaceOptions::audit_log` and read
+//! back with `history`.  This requires the `audit` feature.
+//!
+//! ## Updating At The Next Start
+//!
+//! Alternatively an update can be staged with [`stage_update`] without touching the
+//! running executable at all.  The next launch then swaps it in by calling
+//! [`apply_pending_update`] first thing in `main`.  The staged executable is
+//! verified against its digest before it's applied.
+//!
+//! ## Updating After Exit
+//!
+//! Some applications (for instance GUI or TUI apps) prefer to shut down first and to
+//! have the update applied afterwards.  For this the [`UpdateHelper`] spawns a
+//! detached copy of the executable that waits for the current process to exit before
+//! it moves the new executable in place.  For this to work [`handle_update_helper`]
+//! needs to be called first thing in `main`.
+//!
+//! ```
+//! fn main() {
+//!     self_replace::handle_update_helper();
+//!     // regular application code
+//! }
+//!
+//! fn update() -> Result<(), std::io::Error> {
+//!     self_replace::UpdateHelper::new("/path/to/new/binary").spawn()?;
+//!     std::process::exit(0);
+//! }
+//! ```
+//!
+//! GUI applications can additionally be relaunched into the new version with
+//! [`UpdateHelper::relaunch`].  The relaunched process is detached and has no
+//! terminal, so this is not suitable for console or TUI applications.
+//!
+//! ## Optional Features
+//!
+//! With the `tracing` feature enabled, replacements and deletions emit spans and
+//! debug events through [`tracing`](https://docs.rs/tracing) for each step, such as
+//! the resolved executable, the staging path, the number of bytes copied, the
+//! restored permissions, the final rename and the cleanup.  Without the feature
+//! the crate stays silent.
+//!
+//! The `serde` feature implements `Serialize` and `Deserialize` for the outcome
+//! types such as [`ReplaceOutcome`] and [`DeleteOutcome`], as well as for
+//! [`UninstallManifest`], [`Backup`], [`HealthStatus`] and [`ErrorReport`], so
+//! that results can be reported as JSON without any mapping.  Timestamps are
+//! serialized as milliseconds since the epoch.
+//!
+//! The `audit` feature enables the audit log (`ReplaceOptions::audit_log`,
+//! `DeleteOptions::audit_log` and `history`).  It implies `serde` and pulls in
+//! `serde_json` to read and write the log.
+//!
 //! ## Implementation
 //!
 //! The way this is implemented depends on the operating system.  On UNIX systems you
@@ -111,15 +185,66 @@
 //! recommended to run automatic cleanup on startup as the location of those temporary
 //! files placed is left undefined.  In many cases the temporary files will be placed
 //! in temporary locations and the operating system will take care of the deletion on
-//! restart.
+//! restart.  On Linux the replacement is written to an anonymous file (`O_TMPFILE`)
+//! where supported, which narrows this window down to the final rename.
 use std::io;
 use std::path::Path;
 
+#[cfg(feature = "audit")]
+use crate::audit::AuditLog;
+
+#[macro_use]
+mod trace;
+
+#[cfg(feature = "audit")]
+mod audit;
+mod backup;
+mod bundle;
+#[cfg(unix)]
+mod copy;
+#[cfg(unix)]
+mod dirfd;
+mod error;
+#[cfg(unix)]
+mod exit_hook;
+mod fsutil;
+mod hash;
+mod health;
+mod helper;
+mod hooks;
+#[cfg(unix)]
+mod links;
+mod manifest;
+mod options;
+mod pending;
+mod progress;
+mod replace;
+mod retry;
+mod timestamp;
+mod tree;
 #[cfg(unix)]
 mod unix;
+mod versions;
 #[cfg(windows)]
 mod windows;
 
+#[cfg(feature = "audit")]
+pub use crate::audit::{history, AuditEntry, AuditOperation};
+pub use crate::backup::{list_backups, prune_backups, Backup, RetentionPolicy};
+pub use crate::bundle::Bundle;
+pub use crate::error::ErrorReport;
+pub use crate::health::{check_health, mark_healthy, HealthStatus};
+pub use crate::helper::{handle_update_helper, UpdateHelper};
+pub use crate::hooks::{HookContext, HookOutcome, Hooks, Operation};
+pub use crate::manifest::{self_uninstall, ManifestEntry, UninstallManifest, UninstallReport};
+pub use crate::options::{
+    DeleteOptions, DeleteOutcome, ReplaceOptions, ReplaceOutcome, StagingStrategy,
+};
+pub use crate::pending::{apply_pending_update, stage_update};
+pub use crate::progress::{CancellationToken, Progress, ProgressPhase};
+pub use crate::retry::RetryPolicy;
+pub use crate::versions::VersionedInstall;
+
 #[cfg(not(any(windows, unix)))]
 compile_error!("self-replace cannot be built for this target (only windows and unix is supported)");
 
@@ -146,21 +271,106 @@ pub fn self_delete() -> Result<(), io::Error> {
 /// to the temporary files.  They are always based on the original, reported
 /// file name of the current executable.
 pub fn self_delete_at<P: AsRef<Path>>(exe: P) -> Result<(), io::Error> {
+    let _span = span!("self_delete", exe = %exe.as_ref().display());
+    #[cfg(unix)]
+    let rv = crate::unix::self_delete(exe.as_ref());
+    #[cfg(windows)]
+    let rv = crate::windows::self_delete(exe.as_ref(), None);
+    #[cfg(not(any(windows, unix)))]
+    let rv = {
+        let _ = exe;
+        unimplemented!()
+    };
+    debug!(result = ?rv, "finished deleting");
+    rv
+}
+
+/// Like [`self_delete`] but configurable with [`DeleteOptions`].
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// use self_replace::DeleteOptions;
+/// let outcome = self_replace::self_delete_with(&DeleteOptions::new().deferred(true))?;
+/// assert!(outcome.deferred);
+/// # Ok(()) }
+/// ```
+pub fn self_delete_with(options: &DeleteOptions) -> Result<DeleteOutcome, io::Error> {
+    let exe = match options.executable {
+        Some(ref exe) => exe.clone(),
+        None => std::env::current_exe()?,
+    };
+    let _span = span!("self_delete", exe = %exe.display());
+    #[cfg(feature = "audit")]
+    let audit = match options.audit_log {
+        Some(ref log) => Some(AuditLog::open(log, AuditOperation::Delete, &exe, None)?),
+        None => None,
+    };
+    let rv = platform_delete(&exe, options);
+    #[cfg(feature = "audit")]
+    {
+        if let Some(audit) = audit {
+            audit.finish(&rv);
+        }
+    }
+    debug!(result = ?rv, "finished deleting");
+    if let Some(ref hooks) = options.hooks {
+        let ctx = HookContext {
+            operation: Operation::Delete,
+            executable: &exe,
+            new_executable: None,
+        };
+        match rv {
+            Ok(ref outcome) => hooks.0.post_commit(&ctx, HookOutcome::Delete(outcome)),
+            Err(ref err) => hooks.0.on_error(&ctx, err),
+        }
+    }
+    rv
+}
+
+fn platform_delete(exe: &Path, options: &DeleteOptions) -> Result<DeleteOutcome, io::Error> {
     #[cfg(unix)]
     {
-        crate::unix::self_delete(exe.as_ref())
+        crate::unix::self_delete_with(exe, options)
     }
     #[cfg(windows)]
     {
-        crate::windows::self_delete(exe.as_ref(), None)
+        let ctx = HookContext {
+            operation: Operation::Delete,
+            executable: exe,
+            new_executable: None,
+        };
+        crate::hooks::pre_commit(options.hooks.as_ref(), &ctx)?;
+        crate::windows::self_delete(exe, None)?;
+        Ok(DeleteOutcome {
+            deferred: true,
+            removed_links: Vec::new(),
+            remaining_hardlinks: Vec::new(),
+        })
     }
     #[cfg(not(any(windows, unix)))]
     {
         let _ = exe;
+        let _ = options;
         unimplemented!();
     }
 }
 
+/// Revokes a deletion that was deferred with [`DeleteOptions::deferred`].
+///
+/// Returns `true` if a pending deletion was cancelled.  This is useful if an
+/// uninstallation is aborted after the deletion was already scheduled.  On Windows
+/// a scheduled deletion cannot be revoked and this always returns `false`.
+pub fn cancel_self_delete() -> bool {
+    #[cfg(unix)]
+    {
+        crate::exit_hook::cancel_delete()
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
 /// Like [`self_delete`] but accepts a path which must not be used for temporary operations.
 ///
 /// This is equivalent to [`self_delete`] on Unix, but it instructs the deletion logic to
@@ -169,6 +379,7 @@ pub fn self_delete_at<P: AsRef<Path>>(exe: P) -> Result<(), io::Error> {
 /// structures on Windows.
 pub fn self_delete_outside_path<P: AsRef<Path>>(p: P) -> Result<(), io::Error> {
     let exe = std::env::current_exe()?;
+    let _span = span!("self_delete", exe = %exe.display());
     #[cfg(unix)]
     {
         let _ = p;
@@ -186,6 +397,37 @@ pub fn self_delete_outside_path<P: AsRef<Path>>(p: P) -> Result<(), io::Error> {
     }
 }
 
+/// Deletes the executable together with the install root that contains it.
+///
+/// This is the one-stop solution for uninstalling an application which lives in its
+/// own folder.  The executable is deleted like with [`self_delete_outside_path`] and
+/// then `root` is removed recursively.  As a safety measure the function refuses to
+/// delete the root of the file system, the home directory (or any of its parents),
+/// or a folder that does not contain the current executable.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// let itself = std::env::current_exe()?;
+/// self_replace::self_delete_tree(itself.parent().unwrap())?;
+/// # Ok(()) }
+/// ```
+pub fn self_delete_tree<P: AsRef<Path>>(root: P) -> Result<(), io::Error> {
+    crate::tree::self_delete_tree(root.as_ref(), None)
+}
+
+/// Like [`self_delete_tree`] but also removes parent folders that were left empty.
+///
+/// Parent folders of `root` are removed for as long as they are empty, stopping at
+/// `boundary` which itself is never removed.  The boundary must be a parent of the
+/// install root.  This is useful for layouts like `~/.local/share/vendor/app` where
+/// the `vendor` folder should go away together with the last of its applications.
+pub fn self_delete_tree_until<P: AsRef<Path>, B: AsRef<Path>>(
+    root: P,
+    boundary: B,
+) -> Result<(), io::Error> {
+    crate::tree::self_delete_tree(root.as_ref(), Some(boundary.as_ref()))
+}
+
 /// Replaces the running executable with a different one.
 ///
 /// This replaces the binary with another binary.  The provided path is copied over and
@@ -208,9 +450,48 @@ pub fn self_delete_outside_path<P: AsRef<Path>>(p: P) -> Result<(), io::Error> {
 ///
 /// By default the permissions of the original file are restored.
 pub fn self_replace<P: AsRef<Path>>(new_executable: P) -> Result<(), io::Error> {
+    let _span = span!(
+        "self_replace",
+        new_executable = %new_executable.as_ref().display()
+    );
+    #[cfg(unix)]
+    let rv = crate::unix::self_replace(new_executable.as_ref());
+    #[cfg(windows)]
+    let rv = crate::windows::self_replace(new_executable.as_ref());
+    #[cfg(not(any(windows, unix)))]
+    let rv = {
+        let _ = new_executable;
+        unimplemented!()
+    };
+    debug!(result = ?rv, "finished replacing");
+    rv
+}
+
+/// Replaces the running executable with another one once the process exits.
+///
+/// The new executable is checked and copied next to the current one right away, so
+/// the source can be deleted after this returns.  Only the final move into place
+/// is deferred until the process exits normally (by returning from `main` or by
+/// calling [`std::process::exit`]), or on a best effort basis when it's
+/// terminated with `SIGTERM` or `SIGINT`.  This is useful for tools that must not
+/// change underneath a running command.  The replacement can be queried with
+/// [`is_self_replace_pending`] and revoked with [`cancel_self_replace`].  Calling
+/// this again replaces a previously scheduled replacement.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// self_replace::self_replace_on_exit("/path/to/new/binary")?;
+/// assert!(self_replace::is_self_replace_pending());
+/// # Ok(()) }
+/// ```
+///
+/// If the process is killed forcefully, the staged copy is left behind.  On
+/// Windows the replacement already is only finalized at shutdown, so this works
+/// like [`self_replace`] and nothing is left pending.
+pub fn self_replace_on_exit<P: AsRef<Path>>(new_executable: P) -> Result<(), io::Error> {
     #[cfg(unix)]
     {
-        crate::unix::self_replace(new_executable.as_ref())
+        crate::unix::self_replace_on_exit(new_executable.as_ref())
     }
     #[cfg(windows)]
     {
@@ -222,3 +503,49 @@ pub fn self_replace<P: AsRef<Path>>(new_executable: P) -> Result<(), io::Error>
         unimplemented!();
     }
 }
+
+/// Returns `true` if a replacement scheduled by [`self_replace_on_exit`] is pending.
+pub fn is_self_replace_pending() -> bool {
+    #[cfg(unix)]
+    {
+        crate::exit_hook::has_pending_rename()
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Revokes a replacement scheduled by [`self_replace_on_exit`].
+///
+/// Returns `true` if there was a pending replacement.  On Windows this always
+/// returns `false` as the replacement cannot be revoked.
+pub fn cancel_self_replace() -> bool {
+    #[cfg(unix)]
+    {
+        crate::unix::cancel_self_replace()
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Like [`self_replace`] but configurable with [`ReplaceOptions`].
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// use self_replace::ReplaceOptions;
+/// let options = ReplaceOptions::new().update_hardlinks(true);
+/// let outcome = self_replace::self_replace_with("/path/to/new/binary", &options)?;
+/// for link in &outcome.hardlinks {
+///     println!("also updated {}", link.display());
+/// }
+/// # Ok(()) }
+/// ```
+pub fn self_replace_with<P: AsRef<Path>>(
+    new_executable: P,
+    options: &ReplaceOptions,
+) -> Result<ReplaceOutcome, io::Error> {
+    crate::replace::self_replace_with(new_executable.as_ref(), options)
+}
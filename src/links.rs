@@ -0,0 +1,106 @@
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, MetadataExt};
+use std::path::{Path, PathBuf};
+
+/// Other names under which an executable is reachable.
+#[derive(Debug, Default)]
+pub struct Links {
+    pub symlinks: Vec<PathBuf>,
+    pub hardlinks: Vec<PathBuf>,
+}
+
+/// Returns the directories on `PATH`.
+pub fn path_dirs() -> Vec<PathBuf> {
+    env::var_os("PATH")
+        .map(|x| env::split_paths(&x).collect())
+        .unwrap_or_default()
+}
+
+/// Finds symlinks and hardlinks to `exe` in the given directories.
+///
+/// The executable path must be canonical.  Directories that cannot be read are
+/// skipped as they commonly show up on `PATH`.
+pub fn find_links(exe: &Path, dirs: &[PathBuf]) -> Result<Links, io::Error> {
+    let exe_md = fs::metadata(exe)?;
+    let mut seen = HashSet::new();
+    let mut rv = Links::default();
+
+    for dir in dirs {
+        let dir = match dir.canonicalize() {
+            Ok(dir) => dir,
+            Err(_) => continue,
+        };
+        if !seen.insert(dir.clone()) {
+            continue;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path == exe {
+                continue;
+            }
+            let md = match fs::symlink_metadata(&path) {
+                Ok(md) => md,
+                Err(_) => continue,
+            };
+            if md.file_type().is_symlink() {
+                if path.canonicalize().ok().as_deref() == Some(exe) {
+                    rv.symlinks.push(path);
+                }
+            } else if md.is_file() && md.dev() == exe_md.dev() && md.ino() == exe_md.ino() {
+                rv.hardlinks.push(path);
+            }
+        }
+    }
+
+    Ok(rv)
+}
+
+/// Reconciles the applet symlinks in `dir` against the given list of names.
+///
+/// Missing applets are added as symlinks to `exe`, symlinks to `exe` which are no
+/// longer in the list are removed.  Files that are not a symlink to the executable
+/// are never touched.  The added and removed links are recorded as they happen,
+/// so they are complete even if this fails halfway.
+pub fn reconcile_applets(
+    exe: &Path,
+    dir: &Path,
+    names: &[OsString],
+    added: &mut Vec<PathBuf>,
+    removed: &mut Vec<PathBuf>,
+) -> Result<(), io::Error> {
+    // applets in the same folder point to the executable relatively so that the
+    // installation stays relocatable.
+    let target = match exe.file_name() {
+        Some(name) if dir.canonicalize().ok().as_deref() == exe.parent() => PathBuf::from(name),
+        _ => exe.to_path_buf(),
+    };
+
+    for name in names {
+        let link = dir.join(name);
+        if link == exe || fs::symlink_metadata(&link).is_ok() {
+            continue;
+        }
+        symlink(&target, &link)?;
+        added.push(link);
+    }
+
+    for link in find_links(exe, &[dir.to_path_buf()])?.symlinks {
+        if link
+            .file_name()
+            .map_or(false, |x| !names.iter().any(|name| name == x))
+        {
+            fs::remove_file(&link)?;
+            removed.push(link);
+        }
+    }
+
+    Ok(())
+}
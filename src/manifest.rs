@@ -0,0 +1,276 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::fsutil::sibling_temp_path;
+use crate::hash::sha256_file;
+
+static HEADER: &str = "# self-replace uninstall manifest";
+
+/// A single entry of an [`UninstallManifest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum ManifestEntry {
+    /// A file which is removed on uninstallation.
+    ///
+    /// If a digest is recorded, the file is only removed if it was not modified
+    /// since it was installed.
+    File {
+        path: PathBuf,
+        sha256: Option<String>,
+    },
+    /// A directory which is removed recursively on uninstallation.
+    Dir { path: PathBuf },
+}
+
+impl ManifestEntry {
+    /// Returns the path of the entry.
+    pub fn path(&self) -> &Path {
+        match *self {
+            ManifestEntry::File { ref path, .. } | ManifestEntry::Dir { ref path } => path,
+        }
+    }
+}
+
+/// Lists all files an application installed outside of its executable.
+///
+/// Applications frequently place shell completions, man pages, desktop files or
+/// configuration stubs in various places of the file system.  The manifest records
+/// these at installation time so that [`self_uninstall`](crate::self_uninstall) can
+/// remove them again together with the executable.
+///
+/// The manifest can be stored in a simple line based text format, with one entry
+/// per line:
+///
+/// ```text
+/// # self-replace uninstall manifest
+/// file 2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae /usr/share/man/man1/tool.1
+/// file - /home/user/.config/tool/config.toml
+/// dir /home/user/.local/share/tool
+/// ```
+///
+/// Files with a digest are only removed if they were not modified, files with `-`
+/// as digest are removed unconditionally.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UninstallManifest {
+    entries: Vec<ManifestEntry>,
+}
+
+impl UninstallManifest {
+    /// Creates an empty manifest.
+    pub fn new() -> UninstallManifest {
+        UninstallManifest::default()
+    }
+
+    /// Parses a manifest from its text representation.
+    pub fn parse(s: &str) -> Result<UninstallManifest, io::Error> {
+        let mut rv = UninstallManifest::new();
+        for line in s.lines() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (kind, rest) = split_word(line);
+            let entry = match kind {
+                "file" => {
+                    let (sha256, path) = split_word(rest);
+                    ManifestEntry::File {
+                        path: parse_path(path)?,
+                        sha256: match sha256 {
+                            "-" => None,
+                            other => Some(other.to_string()),
+                        },
+                    }
+                }
+                "dir" => ManifestEntry::Dir {
+                    path: parse_path(rest)?,
+                },
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid manifest line: {line}"),
+                    ))
+                }
+            };
+            rv.entries.push(entry);
+        }
+        Ok(rv)
+    }
+
+    /// Loads a manifest from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<UninstallManifest, io::Error> {
+        UninstallManifest::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Atomically writes the manifest to a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        let path = path.as_ref();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let tmp = tempfile::Builder::new()
+            .prefix(".manifest.__temp__")
+            .tempfile_in(dir)?;
+        fs::write(tmp.path(), self.to_string())?;
+        tmp.persist(path)?;
+        Ok(())
+    }
+
+    /// Adds a file which is only removed if it's unchanged at uninstallation time.
+    ///
+    /// The digest of the file is calculated right away, so this needs to be called
+    /// after the file was written.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self, io::Error> {
+        let path = absolute_path(path.as_ref())?;
+        let sha256 = sha256_file(&path)?;
+        self.entries.push(ManifestEntry::File {
+            path,
+            sha256: Some(sha256),
+        });
+        Ok(self)
+    }
+
+    /// Adds a file which is removed no matter if it was modified.
+    pub fn add_file_unchecked<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self, io::Error> {
+        let path = absolute_path(path.as_ref())?;
+        self.entries
+            .push(ManifestEntry::File { path, sha256: None });
+        Ok(self)
+    }
+
+    /// Adds a directory which is removed together with its contents.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self, io::Error> {
+        let path = absolute_path(path.as_ref())?;
+        self.entries.push(ManifestEntry::Dir { path });
+        Ok(self)
+    }
+
+    /// Returns all entries in the manifest.
+    pub fn entries(&self) -> &[ManifestEntry] {
+        &self.entries
+    }
+}
+
+impl fmt::Display for UninstallManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{HEADER}")?;
+        for entry in &self.entries {
+            match *entry {
+                ManifestEntry::File {
+                    ref path,
+                    ref sha256,
+                } => writeln!(
+                    f,
+                    "file {} {}",
+                    sha256.as_deref().unwrap_or("-"),
+                    path.display()
+                )?,
+                ManifestEntry::Dir { ref path } => writeln!(f, "dir {}", path.display())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Describes what [`self_uninstall`](crate::self_uninstall) did.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct UninstallReport {
+    /// The entries that were removed.
+    pub removed: Vec<PathBuf>,
+    /// Files that were left alone because they were modified since installation.
+    pub modified: Vec<PathBuf>,
+    /// Entries that no longer existed.
+    pub missing: Vec<PathBuf>,
+}
+
+/// Removes everything listed in the manifest and then deletes the executable.
+///
+/// The executable itself should not be listed in the manifest, and neither should
+/// the folder containing it.  To get rid of an install root use
+/// [`self_delete_tree`](crate::self_delete_tree) instead.
+///
+/// All entries are first moved aside.  Only once this worked for all of them, and
+/// the executable was deleted, the moved entries are removed for good.  If anything
+/// fails along the way all entries are moved back to where they were.
+pub fn self_uninstall(manifest: &UninstallManifest) -> Result<UninstallReport, io::Error> {
+    let mut report = UninstallReport::default();
+    let mut moved = Vec::new();
+
+    let rv = (|| {
+        for entry in manifest.entries() {
+            let path = entry.path();
+            if fs::symlink_metadata(path).is_err() {
+                report.missing.push(path.to_path_buf());
+                continue;
+            }
+            if let ManifestEntry::File {
+                sha256: Some(ref expected),
+                ..
+            } = *entry
+            {
+                if sha256_file(path)? != *expected {
+                    report.modified.push(path.to_path_buf());
+                    continue;
+                }
+            }
+            let aside = sibling_temp_path(path, "__uninstall__")?;
+            fs::rename(path, &aside)?;
+            moved.push((path.to_path_buf(), aside));
+        }
+        crate::self_delete()
+    })();
+
+    if let Err(err) = rv {
+        for (original, aside) in moved.into_iter().rev() {
+            fs::rename(&aside, &original).ok();
+        }
+        return Err(err);
+    }
+
+    for (original, aside) in moved {
+        if aside.is_dir() {
+            fs::remove_dir_all(&aside).ok();
+        } else {
+            fs::remove_file(&aside).ok();
+        }
+        report.removed.push(original);
+    }
+    Ok(report)
+}
+
+fn absolute_path(path: &Path) -> Result<PathBuf, io::Error> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    if path.to_str().map_or(true, |x| x.contains('\n')) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "only unicode paths without newlines can be stored in a manifest",
+        ));
+    }
+    Ok(path)
+}
+
+fn parse_path(s: &str) -> Result<PathBuf, io::Error> {
+    if s.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing path in manifest",
+        ));
+    }
+    Ok(PathBuf::from(s))
+}
+
+fn split_word(s: &str) -> (&str, &str) {
+    match s.find(' ') {
+        Some(idx) => (&s[..idx], &s[idx + 1..]),
+        None => (s, ""),
+    }
+}
@@ -0,0 +1,476 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::backup::RetentionPolicy;
+use crate::error::ErrorReport;
+use crate::hooks::{Hooks, SharedHooks};
+use crate::progress::{CancellationToken, Monitor, Progress, ProgressCallback};
+use crate::retry::RetryPolicy;
+
+/// Options for [`self_delete_with`](crate::self_delete_with).
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// use self_replace::DeleteOptions;
+/// self_replace::self_delete_with(&DeleteOptions::new().deferred(true))?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DeleteOptions {
+    pub(crate) executable: Option<PathBuf>,
+    pub(crate) deferred: bool,
+    pub(crate) remove_links: bool,
+    pub(crate) search_dirs: Vec<PathBuf>,
+    pub(crate) link_only: bool,
+    pub(crate) hooks: Option<SharedHooks>,
+    #[cfg(feature = "audit")]
+    pub(crate) audit_log: Option<PathBuf>,
+}
+
+impl DeleteOptions {
+    /// Creates the default options which delete the current executable immediately.
+    pub fn new() -> DeleteOptions {
+        DeleteOptions::default()
+    }
+
+    /// Overrides the path which is assumed to be the current executable.
+    ///
+    /// This works like [`self_delete_at`](crate::self_delete_at).
+    pub fn executable<P: AsRef<Path>>(mut self, exe: P) -> DeleteOptions {
+        self.executable = Some(exe.as_ref().to_path_buf());
+        self
+    }
+
+    /// Defers the deletion until the process shuts down.
+    ///
+    /// On Unix the executable is otherwise unlinked immediately.  With this enabled
+    /// the pending deletion is recorded and performed when the process exits normally
+    /// (by returning from `main` or by calling [`std::process::exit`]), and on a best
+    /// effort basis when it's terminated with `SIGTERM` or `SIGINT`.  The deletion can
+    /// be revoked with [`cancel_self_delete`](crate::cancel_self_delete).
+    ///
+    /// On Windows the deletion always happens at shutdown so this has no effect.
+    pub fn deferred(mut self, yes: bool) -> DeleteOptions {
+        self.deferred = yes;
+        self
+    }
+
+    /// Also removes links that point to the executable.
+    ///
+    /// Executables are frequently made available on the `PATH` through symlinks
+    /// (for instance in `~/.local/bin`) which would be left dangling.  With this
+    /// enabled all symlinks and hardlinks to the executable in its own folder, the
+    /// directories on `PATH` and those added with [`search_dir`](Self::search_dir)
+    /// are removed too.
+    ///
+    /// This is only supported on Unix.
+    pub fn remove_links(mut self, yes: bool) -> DeleteOptions {
+        self.remove_links = yes;
+        self
+    }
+
+    /// Adds a directory to look for links in addition to the `PATH`.
+    ///
+    /// If the executable has other hardlinks, these directories are also searched
+    /// to report them in [`DeleteOutcome::remaining_hardlinks`].
+    pub fn search_dir<P: AsRef<Path>>(mut self, dir: P) -> DeleteOptions {
+        self.search_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Only deletes the link if the executable path is a symlink.
+    ///
+    /// Normally a symlink is resolved and the executable it points to is deleted.
+    /// With this enabled and a symlink passed to [`executable`](Self::executable),
+    /// only the symlink itself is removed and the executable stays in place.
+    pub fn link_only(mut self, yes: bool) -> DeleteOptions {
+        self.link_only = yes;
+        self
+    }
+
+    /// Invokes the given [`Hooks`] while deleting.
+    ///
+    /// [`Hooks::pre_commit`] is invoked before anything is deleted (or scheduled
+    /// for deletion) and can veto the deletion.
+    pub fn hooks<H: Hooks + 'static>(mut self, hooks: H) -> DeleteOptions {
+        self.hooks = Some(SharedHooks(Arc::new(hooks)));
+        self
+    }
+
+    /// Records the deletion in an audit log.
+    ///
+    /// See [`ReplaceOptions::audit_log`] for details.
+    #[cfg(feature = "audit")]
+    pub fn audit_log<P: AsRef<Path>>(mut self, log: P) -> DeleteOptions {
+        self.audit_log = Some(log.as_ref().to_path_buf());
+        self
+    }
+}
+
+/// Describes what [`self_delete_with`](crate::self_delete_with) did.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct DeleteOutcome {
+    /// `true` if the executable is only deleted once the process shuts down.
+    pub deferred: bool,
+    /// Links to the executable that were removed (or are pending removal).
+    pub removed_links: Vec<PathBuf>,
+    /// Other hardlinks of the executable which were found but not removed.
+    ///
+    /// If this is not empty, the contents of the executable are still installed
+    /// under these names.  Use [`DeleteOptions::remove_links`] to remove them too.
+    pub remaining_hardlinks: Vec<PathBuf>,
+}
+
+/// Options for [`self_replace_with`](crate::self_replace_with).
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// use self_replace::ReplaceOptions;
+/// let options = ReplaceOptions::new().update_hardlinks(true);
+/// self_replace::self_replace_with("/path/to/new/binary", &options)?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ReplaceOptions {
+    pub(crate) update_hardlinks: bool,
+    pub(crate) search_dirs: Vec<PathBuf>,
+    pub(crate) applets: Option<Vec<OsString>>,
+    pub(crate) applet_dir: Option<PathBuf>,
+    pub(crate) backup: bool,
+    pub(crate) running_version: Option<String>,
+    pub(crate) health_check: Option<u32>,
+    pub(crate) retention: Option<RetentionPolicy>,
+    pub(crate) block_signals: bool,
+    pub(crate) hardened: bool,
+    pub(crate) consume_source: bool,
+    pub(crate) verify: bool,
+    pub(crate) in_place_fallback: bool,
+    pub(crate) retry: Option<RetryPolicy>,
+    pub(crate) progress: Option<ProgressCallback>,
+    pub(crate) cancellation: Option<CancellationToken>,
+    pub(crate) hooks: Option<SharedHooks>,
+    #[cfg(feature = "audit")]
+    pub(crate) new_version: Option<String>,
+    #[cfg(feature = "audit")]
+    pub(crate) audit_log: Option<PathBuf>,
+}
+
+impl ReplaceOptions {
+    /// Creates the default options which behave like [`self_replace`](crate::self_replace).
+    pub fn new() -> ReplaceOptions {
+        ReplaceOptions::default()
+    }
+
+    /// Updates all other hardlinks of the executable too.
+    ///
+    /// If the executable has more than one name (for instance in multi-call
+    /// installations or because a package cache hardlinks it) replacing one name
+    /// splits it off from the others which keep the old contents.  With this enabled
+    /// the other names that could be found are pointed to the new executable as well.
+    /// Hardlinks are searched for in the folder of the executable and the folders
+    /// added with [`search_dir`](Self::search_dir).  Hardlinks that cannot be
+    /// updated are reported in [`ReplaceOutcome::hardlink_errors`].
+    ///
+    /// This is only supported on Unix.
+    pub fn update_hardlinks(mut self, yes: bool) -> ReplaceOptions {
+        self.update_hardlinks = yes;
+        self
+    }
+
+    /// Adds a directory to look for hardlinks of the executable.
+    pub fn search_dir<P: AsRef<Path>>(mut self, dir: P) -> ReplaceOptions {
+        self.search_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Reconciles the applet symlinks of a multi-call executable.
+    ///
+    /// Busybox-style tools are installed as a single executable with many symlinks
+    /// pointing to it.  After the executable was replaced, the symlinks are made to
+    /// match the given list of applet names (as supported by the new version):
+    /// missing applets are added, symlinks to the executable which are no longer in
+    /// the list are removed.  Files which are not a symlink to the executable are
+    /// left alone.  The applets are expected next to the executable unless changed
+    /// with [`applet_dir`](Self::applet_dir).
+    ///
+    /// This is only supported on Unix.
+    pub fn applet_links<I, S>(mut self, names: I) -> ReplaceOptions
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.applets = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Sets the folder in which the applet symlinks live.
+    pub fn applet_dir<P: AsRef<Path>>(mut self, dir: P) -> ReplaceOptions {
+        self.applet_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Keeps a backup of the executable that is replaced.
+    ///
+    /// The backup is placed in a hidden `.{name}.backups` folder next to the
+    /// executable and reported in [`ReplaceOutcome::backup`].
+    pub fn backup(mut self, yes: bool) -> ReplaceOptions {
+        self.backup = yes;
+        self
+    }
+
+    /// Records the version of the running executable with its backup.
+    pub fn running_version<S: Into<String>>(mut self, version: S) -> ReplaceOptions {
+        self.running_version = Some(version.into());
+        self
+    }
+
+    /// Requires the new version to confirm its health within `max_launches`.
+    ///
+    /// This implies [`backup`](Self::backup).  The new version has to call
+    /// [`mark_healthy`](crate::mark_healthy) once it's known to work, otherwise
+    /// [`check_health`](crate::check_health) restores the backup once the new
+    /// version was launched more than `max_launches` times.
+    pub fn health_check(mut self, max_launches: u32) -> ReplaceOptions {
+        self.health_check = Some(max_launches);
+        self
+    }
+
+    /// Prunes old backups with the given policy after a successful update.
+    ///
+    /// This works like [`prune_backups`](crate::prune_backups).  The backup made
+    /// by this update is always kept.
+    pub fn retention(mut self, policy: RetentionPolicy) -> ReplaceOptions {
+        self.retention = Some(policy);
+        self
+    }
+
+    /// Holds back termination signals while the replacement is committed.
+    ///
+    /// If the process is interrupted (for instance with Ctrl-C) right between
+    /// persisting the new executable and moving it into place, the temporary file
+    /// is left behind.  With this enabled `SIGINT`, `SIGTERM` and `SIGHUP` are
+    /// blocked for the duration of these steps and delivered right after, so the
+    /// process can only be interrupted at safe points.
+    ///
+    /// This applies to the whole process: a handler which only records the signals
+    /// is installed in the meantime, and afterwards the previous handlers are
+    /// restored and the recorded signals raised again.  Replacing from multiple
+    /// threads at once with this enabled is not supported.
+    ///
+    /// This is only supported on Unix.
+    pub fn block_signals(mut self, yes: bool) -> ReplaceOptions {
+        self.block_signals = yes;
+        self
+    }
+
+    /// Performs the replacement relative to an opened directory.
+    ///
+    /// Normally the path of the executable is resolved once and then used for all
+    /// following file system operations.  If a directory along that path is
+    /// swapped out concurrently (for instance by replacing it with a symlink), the
+    /// replacement could end up somewhere else.  With this enabled the folder of
+    /// the executable is opened without following any symlinks and all operations
+    /// are performed relative to it (`openat`, `renameat` and friends).  The
+    /// executable is additionally verified to still be the same file right before
+    /// the new one is moved into place, otherwise the replacement is aborted.
+    ///
+    /// This is only supported on Unix.
+    pub fn hardened(mut self, yes: bool) -> ReplaceOptions {
+        self.hardened = yes;
+        self
+    }
+
+    /// Allows the new executable to be moved into place instead of copied.
+    ///
+    /// By default the new executable is copied, so the source stays untouched.
+    /// For large executables this is slow and doubles the amount of data written.
+    /// With this enabled the source is renamed into place if it's on the same file
+    /// system as the executable, in which case it's gone afterwards.  If it cannot
+    /// be moved (for instance because a bind mount is in between) it's copied
+    /// after all.  The strategy that was used is reported in
+    /// [`ReplaceOutcome::staging`].  This has no effect in
+    /// [`hardened`](Self::hardened) mode.
+    ///
+    /// This is only supported on Unix.
+    pub fn consume_source(mut self, yes: bool) -> ReplaceOptions {
+        self.consume_source = yes;
+        self
+    }
+
+    /// Verifies the digest of the staged executable before committing it.
+    ///
+    /// The size of the staged executable is always compared with the new
+    /// executable, and the replacement is refused upfront with `ENOSPC` if the file
+    /// system does not have enough room for it.  With this enabled the contents
+    /// are additionally hashed and compared, which requires reading both files once
+    /// more.
+    ///
+    /// This is only supported on Unix.
+    pub fn verify(mut self, yes: bool) -> ReplaceOptions {
+        self.verify = yes;
+        self
+    }
+
+    /// Overwrites the executable in place if it cannot be renamed over.
+    ///
+    /// If the executable is a bind-mounted file (which is common in containers)
+    /// renaming over it fails with `EBUSY`, and some FUSE file systems do not
+    /// support renames at all.  With this enabled the contents of the executable
+    /// are overwritten instead.  This is not atomic and only possible if the file
+    /// is not currently executed.  If it is (which is usually the case when a
+    /// program replaces itself), the write is left to a copy of the executable
+    /// which waits for the process to exit, see [`UpdateHelper`].  For this to work
+    /// [`handle_update_helper`] needs to be called at the start of `main`.  This
+    /// has no effect in [`hardened`](Self::hardened) mode.
+    ///
+    /// This is only supported on Unix.
+    ///
+    /// [`UpdateHelper`]: crate::UpdateHelper
+    /// [`handle_update_helper`]: crate::handle_update_helper
+    pub fn in_place_fallback(mut self, yes: bool) -> ReplaceOptions {
+        self.in_place_fallback = yes;
+        self
+    }
+
+    /// Retries the individual steps on transient errors.
+    ///
+    /// See [`RetryPolicy`] for details.  This is only supported on Unix.
+    pub fn retry(mut self, policy: RetryPolicy) -> ReplaceOptions {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Reports the progress of copying and verifying the new executable.
+    ///
+    /// The callback is invoked on the calling thread between chunks of work.
+    /// This is only supported on Unix.
+    pub fn progress<F>(mut self, f: F) -> ReplaceOptions
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(ProgressCallback(Arc::new(f)));
+        self
+    }
+
+    /// Aborts the replacement once the token is cancelled.
+    ///
+    /// The token is checked between chunks of work and a last time right before
+    /// the new executable is committed.  A cancelled replacement fails and removes
+    /// everything it staged.  See [`CancellationToken`] for details.  This is only
+    /// supported on Unix.
+    pub fn cancellation(mut self, token: CancellationToken) -> ReplaceOptions {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Invokes the given [`Hooks`] at each step of the replacement.
+    ///
+    /// The hooks can veto the replacement before it's committed and observe its
+    /// outcome or error.
+    pub fn hooks<H: Hooks + 'static>(mut self, hooks: H) -> ReplaceOptions {
+        self.hooks = Some(SharedHooks(Arc::new(hooks)));
+        self
+    }
+
+    /// Records the version of the new executable.
+    ///
+    /// This is only used for the [`audit_log`](Self::audit_log).
+    #[cfg(feature = "audit")]
+    pub fn new_version<S: Into<String>>(mut self, version: S) -> ReplaceOptions {
+        self.new_version = Some(version.into());
+        self
+    }
+
+    /// Records the replacement in an append-only audit log.
+    ///
+    /// Every replacement, successful or not, appends a line of JSON to the log with
+    /// the time, the digests and versions (see [`running_version`](Self::running_version)
+    /// and [`new_version`](Self::new_version)) of the old and new executable, the
+    /// user and the error if any.  Rollbacks by [`check_health`](crate::check_health)
+    /// are recorded in the same log.  The log can be read back with
+    /// [`history`](crate::history).
+    ///
+    /// If the log cannot be opened, the replacement is not attempted.
+    ///
+    /// This requires the `audit` feature.
+    #[cfg(feature = "audit")]
+    pub fn audit_log<P: AsRef<Path>>(mut self, log: P) -> ReplaceOptions {
+        self.audit_log = Some(log.as_ref().to_path_buf());
+        self
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
+    pub(crate) fn monitor(&self) -> Monitor<'_> {
+        Monitor {
+            callback: self.progress.as_ref(),
+            token: self.cancellation.as_ref(),
+        }
+    }
+}
+
+/// Describes what [`self_replace_with`](crate::self_replace_with) did.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct ReplaceOutcome {
+    /// Other hardlinks of the replaced executable that were found.
+    pub hardlinks: Vec<PathBuf>,
+    /// `true` if all hardlinks were updated to the new executable.
+    pub hardlinks_updated: bool,
+    /// The hardlinks that could not be updated, with the error.
+    ///
+    /// Hardlinks are updated after the new executable is in place, so a failure
+    /// does not fail the replacement.
+    pub hardlink_errors: Vec<(PathBuf, ErrorReport)>,
+    /// Applet symlinks that were added.
+    pub applets_added: Vec<PathBuf>,
+    /// Obsolete applet symlinks that were removed.
+    pub applets_removed: Vec<PathBuf>,
+    /// The error if reconciling the applet symlinks failed.
+    ///
+    /// The applets are reconciled after the new executable is in place, so a
+    /// failure does not fail the replacement.  The applets added and removed until
+    /// then are still reported.
+    pub applet_error: Option<ErrorReport>,
+    /// The backup of the replaced executable if one was kept.
+    pub backup: Option<PathBuf>,
+    /// Old backups that were removed because of [`ReplaceOptions::retention`].
+    pub pruned_backups: Vec<PathBuf>,
+    /// The error if pruning old backups failed.
+    ///
+    /// Pruning happens after the new executable is in place, so a failure does not
+    /// fail the replacement.
+    pub prune_error: Option<ErrorReport>,
+    /// How the new executable was put next to the old one.
+    pub staging: StagingStrategy,
+    /// `true` if the executable was overwritten in place.
+    ///
+    /// See [`ReplaceOptions::in_place_fallback`].
+    pub in_place: bool,
+    /// `true` if overwriting the executable was left to a helper that runs once
+    /// the process exited.
+    pub deferred: bool,
+    /// How many times a step was retried because of [`ReplaceOptions::retry`].
+    pub retries: u32,
+}
+
+/// How the new executable was staged, see [`ReplaceOutcome::staging`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum StagingStrategy {
+    /// The contents were copied through user space.
+    #[default]
+    Copy,
+    /// The contents were copied within the kernel with `copy_file_range`.
+    CopyFileRange,
+    /// The file was cloned with a reflink, sharing the data on disk.
+    Reflink,
+    /// The source was moved into place with
+    /// [`consume_source`](ReplaceOptions::consume_source).
+    Rename,
+}
@@ -0,0 +1,92 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::fsutil::{current_exe, sibling_path, sibling_temp_path, write_atomic};
+use crate::hash::sha256_file;
+
+/// Stages a new executable to be swapped in at the next start.
+///
+/// The new executable is copied next to the current one as `.{name}.pending`
+/// together with its digest and left there until [`apply_pending_update`] is
+/// called, usually first thing in `main` of the next launch.  This is useful if the
+/// running executable should not be touched at all while it's running.  Staging
+/// again replaces a previously staged update.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// self_replace::stage_update("/path/to/new/binary")?;
+/// # Ok(()) }
+/// ```
+pub fn stage_update<P: AsRef<Path>>(new_executable: P) -> Result<(), io::Error> {
+    let new_executable = new_executable.as_ref();
+    let (pending, digest) = pending_paths(&current_exe()?)?;
+    let expected = sha256_file(new_executable)?;
+
+    let tmp = sibling_temp_path(&pending, "__temp__")?;
+    let rv = (|| {
+        fs::copy(new_executable, &tmp)?;
+        if sha256_file(&tmp)? != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "staged executable does not match its source",
+            ));
+        }
+        // the digest goes first, the pending executable is only visible once
+        // the digest to verify it is in place.
+        write_atomic(&digest, &format!("{expected}\n"))?;
+        fs::rename(&tmp, &pending)
+    })();
+    if rv.is_err() {
+        fs::remove_file(&tmp).ok();
+    }
+    rv
+}
+
+/// Swaps in an update staged with [`stage_update`].
+///
+/// Returns `true` if an update was applied.  The running process is still the old
+/// version at that point, so it should restart itself or exit.  If the staged
+/// executable does not match its recorded digest it's discarded and an error is
+/// returned.  If the process crashes while applying, the update is applied again
+/// on the next call.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// if self_replace::apply_pending_update()? {
+///     eprintln!("updated, please restart");
+///     std::process::exit(0);
+/// }
+/// # Ok(()) }
+/// ```
+pub fn apply_pending_update() -> Result<bool, io::Error> {
+    let (pending, digest) = pending_paths(&current_exe()?)?;
+    if fs::symlink_metadata(&pending).is_err() {
+        return Ok(false);
+    }
+
+    let expected = fs::read_to_string(&digest).unwrap_or_default();
+    if sha256_file(&pending)? != expected.trim() {
+        discard(&pending, &digest);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "pending update does not match its digest",
+        ));
+    }
+
+    crate::self_replace(&pending)?;
+    discard(&pending, &digest);
+    Ok(true)
+}
+
+fn pending_paths(exe: &Path) -> Result<(PathBuf, PathBuf), io::Error> {
+    Ok((
+        sibling_path(exe, "pending")?,
+        sibling_path(exe, "pending.sha256")?,
+    ))
+}
+
+fn discard(pending: &Path, digest: &Path) {
+    fs::remove_file(pending).ok();
+    fs::remove_file(digest).ok();
+}
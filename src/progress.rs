@@ -0,0 +1,110 @@
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The step a replacement is in, see [`Progress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[non_exhaustive]
+pub enum ProgressPhase {
+    /// The new executable is copied next to the old one.
+    Staging,
+    /// The staged executable is hashed and compared with the new executable.
+    Verifying,
+}
+
+/// Reports how far along a long running operation is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Progress {
+    /// The current step.
+    pub phase: ProgressPhase,
+    /// How many bytes of the current step are done.
+    pub done: u64,
+    /// How many bytes the current step processes in total.
+    pub total: u64,
+}
+
+/// Allows aborting a replacement from another thread.
+///
+/// The token is checked between chunks of the copy and verification steps.  A
+/// cancelled replacement fails with an error and removes everything it staged.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// use self_replace::{CancellationToken, ReplaceOptions};
+/// let token = CancellationToken::new();
+/// let options = ReplaceOptions::new()
+///     .cancellation(token.clone())
+///     .progress(|progress| {
+///         println!("{:?}: {}/{}", progress.phase, progress.done, progress.total)
+///     });
+/// // call token.cancel() from somewhere else to abort
+/// self_replace::self_replace_with("/path/to/new/binary", &options)?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Requests the cancellation of all operations using this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if [`cancel`](Self::cancel) was called.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// A progress callback as stored in the options.
+#[derive(Clone)]
+pub(crate) struct ProgressCallback(pub Arc<dyn Fn(Progress) + Send + Sync>);
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// Reports progress and checks for cancellation between chunks.
+#[derive(Clone, Copy)]
+#[cfg_attr(not(unix), allow(dead_code))]
+pub(crate) struct Monitor<'a> {
+    pub callback: Option<&'a ProgressCallback>,
+    pub token: Option<&'a CancellationToken>,
+}
+
+#[cfg_attr(not(unix), allow(dead_code))]
+impl Monitor<'_> {
+    /// Fails if the operation was cancelled, otherwise reports the progress.
+    pub fn report(&self, phase: ProgressPhase, done: u64, total: u64) -> Result<(), io::Error> {
+        self.check()?;
+        if let Some(callback) = self.callback {
+            (callback.0)(Progress { phase, done, total });
+        }
+        Ok(())
+    }
+
+    /// Fails if the operation was cancelled.
+    pub fn check(&self) -> Result<(), io::Error> {
+        if self.token.map_or(false, |x| x.is_cancelled()) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "operation was cancelled",
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std::io;
+use std::path::Path;
+
+#[cfg(feature = "audit")]
+use crate::audit::{AuditLog, AuditOperation};
+use crate::error::ErrorReport;
+use crate::fsutil::current_exe;
+use crate::hooks::{HookContext, HookOutcome, Operation};
+use crate::options::{ReplaceOptions, ReplaceOutcome};
+
+/// Replaces the executable and takes care of the platform independent extras.
+pub fn self_replace_with(
+    new_executable: &Path,
+    options: &ReplaceOptions,
+) -> Result<ReplaceOutcome, io::Error> {
+    let exe = current_exe()?;
+    let _span = span!(
+        "self_replace",
+        exe = %exe.display(),
+        new_executable = %new_executable.display()
+    );
+    let ctx = HookContext {
+        operation: Operation::Replace,
+        executable: &exe,
+        new_executable: Some(new_executable),
+    };
+    #[cfg(feature = "audit")]
+    let audit = match options.audit_log {
+        Some(ref log) => Some(
+            AuditLog::open(log, AuditOperation::Replace, &exe, Some(new_executable))?.versions(
+                options.running_version.as_deref(),
+                options.new_version.as_deref(),
+            ),
+        ),
+        None => None,
+    };
+    let hooks = options.hooks.as_ref();
+    let rv = hooks
+        .map_or(Ok(()), |hooks| hooks.0.pre_stage(&ctx))
+        .and_then(|()| replace(&exe, new_executable, options));
+    debug!(result = ?rv, "finished replacing");
+    #[cfg(feature = "audit")]
+    {
+        if let Some(audit) = audit {
+            audit.finish(&rv);
+        }
+    }
+    if let Some(hooks) = hooks {
+        match rv {
+            Ok(ref outcome) => hooks.0.post_commit(&ctx, HookOutcome::Replace(outcome)),
+            Err(ref err) => hooks.0.on_error(&ctx, err),
+        }
+    }
+    rv
+}
+
+fn replace(
+    exe: &Path,
+    new_executable: &Path,
+    options: &ReplaceOptions,
+) -> Result<ReplaceOutcome, io::Error> {
+    let backup = if options.backup || options.health_check.is_some() {
+        // writing in place would go through a hardlinked backup as well.
+        Some(crate::backup::create_backup(
+            exe,
+            options.running_version.as_deref(),
+            !options.in_place_fallback,
+        )?)
+    } else {
+        None
+    };
+    debug!(backup = ?backup, "prepared backup");
+
+    // everything that can fail the replacement happens before the commit, and
+    // `platform_replace` only fails if nothing was committed.  Once the new
+    // executable is in place the replacement is reported as successful and later
+    // failures (hardlinks, applets, pruning) end up in the outcome, so the health
+    // check and the backup must stay.
+    #[cfg(feature = "audit")]
+    let audit_log = options.audit_log.as_deref();
+    #[cfg(not(feature = "audit"))]
+    let audit_log = None;
+    let rv = match (options.health_check, backup.as_deref()) {
+        (Some(max_launches), Some(backup)) => {
+            crate::health::arm(exe, backup, max_launches, audit_log).map(Some)
+        }
+        _ => Ok(None),
+    }
+    .and_then(|armed| match platform_replace(new_executable, options) {
+        Ok(outcome) => Ok(outcome),
+        Err(err) => {
+            if let Some(armed) = armed {
+                armed.revert();
+            }
+            Err(err)
+        }
+    });
+    let mut outcome = match rv {
+        Ok(outcome) => outcome,
+        Err(err) => {
+            if let Some(ref backup) = backup {
+                crate::backup::remove_backup(backup).ok();
+            }
+            return Err(err);
+        }
+    };
+
+    if let Some(ref policy) = options.retention {
+        match crate::backup::prune(exe, policy, backup.as_deref()) {
+            Ok(pruned) => outcome.pruned_backups = pruned.into_iter().map(|x| x.path).collect(),
+            Err(err) => outcome.prune_error = Some(ErrorReport::from(&err)),
+        }
+    }
+    outcome.backup = backup;
+    Ok(outcome)
+}
+
+fn platform_replace(
+    new_executable: &Path,
+    options: &ReplaceOptions,
+) -> Result<ReplaceOutcome, io::Error> {
+    #[cfg(unix)]
+    {
+        crate::unix::self_replace_with(new_executable, options)
+    }
+    #[cfg(windows)]
+    {
+        let ctx = HookContext {
+            operation: Operation::Replace,
+            executable: &current_exe()?,
+            new_executable: Some(new_executable),
+        };
+        crate::hooks::pre_commit(options.hooks.as_ref(), &ctx)?;
+        crate::windows::self_replace(new_executable)?;
+        Ok(ReplaceOutcome::default())
+    }
+    #[cfg(not(any(windows, unix)))]
+    {
+        let _ = new_executable;
+        let _ = options;
+        unimplemented!();
+    }
+}
@@ -0,0 +1,213 @@
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+/// Decides how often transient file system errors are retried.
+///
+/// Network and overlay file systems occasionally fail renames and copies with
+/// errors like `EBUSY`, `ETXTBSY` or `EAGAIN` that go away on their own.  With a
+/// retry policy passed to [`ReplaceOptions::retry`] each step of a replacement is
+/// retried on such errors, waiting a bit longer after every attempt.  How often
+/// this was necessary is reported in [`ReplaceOutcome::retries`].
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// use std::time::Duration;
+/// use self_replace::{ReplaceOptions, RetryPolicy};
+/// let policy = RetryPolicy::new()
+///     .max_attempts(5)
+///     .backoff(Duration::from_millis(100));
+/// let options = ReplaceOptions::new().retry(policy);
+/// let outcome = self_replace::self_replace_with("/path/to/new/binary", &options)?;
+/// println!("needed {} retries", outcome.retries);
+/// # Ok(()) }
+/// ```
+///
+/// [`ReplaceOptions::retry`]: crate::ReplaceOptions::retry
+/// [`ReplaceOutcome::retries`]: crate::ReplaceOutcome::retries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    kinds: Vec<io::ErrorKind>,
+    os_errors: Vec<i32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            kinds: vec![io::ErrorKind::WouldBlock, io::ErrorKind::Interrupted],
+            #[cfg(unix)]
+            os_errors: vec![libc::EBUSY, libc::ETXTBSY, libc::EAGAIN],
+            #[cfg(not(unix))]
+            os_errors: Vec::new(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy that tries every step up to three times.
+    ///
+    /// By default `EBUSY`, `ETXTBSY` and `EAGAIN` are retried, with a backoff that
+    /// starts at 50ms and doubles after every attempt.
+    pub fn new() -> RetryPolicy {
+        RetryPolicy::default()
+    }
+
+    /// Sets how often a step is attempted in total.
+    pub fn max_attempts(mut self, attempts: u32) -> RetryPolicy {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Sets how long to wait before the first retry.
+    ///
+    /// The wait is doubled after every attempt, up to
+    /// [`max_backoff`](Self::max_backoff).
+    pub fn backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sets the longest time to wait between two attempts.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> RetryPolicy {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Also retries errors of the given kind.
+    pub fn retry_on_kind(mut self, kind: io::ErrorKind) -> RetryPolicy {
+        self.kinds.push(kind);
+        self
+    }
+
+    /// Also retries the given raw operating system error (like `libc::EIO`).
+    pub fn retry_on_os_error(mut self, code: i32) -> RetryPolicy {
+        self.os_errors.push(code);
+        self
+    }
+
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fn is_transient(&self, err: &io::Error) -> bool {
+        self.kinds.contains(&err.kind())
+            || err
+                .raw_os_error()
+                .map_or(false, |code| self.os_errors.contains(&code))
+    }
+
+    /// How long to wait before the given retry (starting at 1).
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fn delay(&self, retry: u32) -> Duration {
+        2u32.checked_pow(retry - 1)
+            .and_then(|factor| self.backoff.checked_mul(factor))
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff))
+    }
+}
+
+/// Runs `f` according to the policy and counts the retries in `retries`.
+///
+/// Without a policy `f` is only run once.
+#[cfg_attr(not(unix), allow(dead_code))]
+pub(crate) fn retry<T, F>(
+    policy: Option<&RetryPolicy>,
+    retries: &mut u32,
+    mut f: F,
+) -> Result<T, io::Error>
+where
+    F: FnMut() -> Result<T, io::Error>,
+{
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return f(),
+    };
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(err) if attempt < policy.max_attempts && policy.is_transient(&err) => {
+                thread::sleep(policy.delay(attempt));
+                attempt += 1;
+                *retries += 1;
+            }
+            rv => return rv,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new()
+            .max_attempts(4)
+            .backoff(Duration::from_millis(1))
+            .max_backoff(Duration::from_millis(1))
+    }
+
+    #[test]
+    fn test_retries_transient_errors() {
+        let mut calls = 0;
+        let mut retries = 0;
+        let rv: Result<(), _> = retry(Some(&policy()), &mut retries, || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::WouldBlock))
+        });
+        assert_eq!(rv.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(calls, 4);
+        assert_eq!(retries, 3);
+
+        let mut calls = 0;
+        let rv = retry(Some(&policy()), &mut retries, || {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from(io::ErrorKind::Interrupted))
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(rv.unwrap(), 3);
+        assert_eq!(retries, 5);
+    }
+
+    #[test]
+    fn test_does_not_retry_other_errors() {
+        let mut calls = 0;
+        let mut retries = 0;
+        let rv: Result<(), _> = retry(Some(&policy()), &mut retries, || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert_eq!(rv.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls, 1);
+        assert_eq!(retries, 0);
+
+        let mut calls = 0;
+        let rv: Result<(), _> = retry(None, &mut retries, || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::WouldBlock))
+        });
+        assert!(rv.is_err());
+        assert_eq!(calls, 1);
+        assert_eq!(retries, 0);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = RetryPolicy::new()
+            .backoff(Duration::from_millis(50))
+            .max_backoff(Duration::from_millis(300));
+        let delays = (1..=6).map(|x| policy.delay(x)).collect::<Vec<_>>();
+        assert_eq!(
+            delays,
+            [50, 100, 200, 300, 300, 300].map(Duration::from_millis)
+        );
+        assert_eq!(policy.delay(100), Duration::from_millis(300));
+
+        let policy = policy.backoff(Duration::from_secs(1));
+        assert_eq!(policy.delay(1), Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Converts a timestamp into milliseconds since the epoch.
+///
+/// This is how timestamps are stored on disk and serialized.  Timestamps before
+/// the epoch are clamped to it.
+pub fn to_millis(timestamp: SystemTime) -> u64 {
+    timestamp
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Converts milliseconds since the epoch into a timestamp.
+pub fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+/// Serializes a [`SystemTime`] as milliseconds since the epoch.
+///
+/// Used with `#[serde(with = "crate::timestamp::millis")]` so that serialized
+/// timestamps match the ones in the audit log and the backup metadata.
+#[cfg(feature = "serde")]
+pub mod millis {
+    use std::time::SystemTime;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        timestamp: &SystemTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(super::to_millis(*timestamp))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        u64::deserialize(deserializer).map(super::from_millis)
+    }
+}
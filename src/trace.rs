@@ -0,0 +1,27 @@
+//! Instrumentation which is forwarded to `tracing` if the feature is enabled.
+//!
+//! Without the feature the macros expand to nothing, so arguments passed to them
+//! must not have side effects.
+
+#[cfg(feature = "tracing")]
+macro_rules! debug {
+    ($($tt:tt)*) => { tracing::debug!($($tt)*) };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($tt:tt)*) => {{}};
+}
+
+/// Enters a span for the rest of the scope and returns its guard.
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($($tt:tt)*) => { tracing::debug_span!($($tt)*).entered() };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($tt:tt)*) => {
+        ()
+    };
+}
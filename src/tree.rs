@@ -0,0 +1,74 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Deletes the executable together with the install root it's placed in.
+pub fn self_delete_tree(root: &Path, boundary: Option<&Path>) -> Result<(), io::Error> {
+    let exe = crate::fsutil::current_exe()?;
+    let root = root.canonicalize()?;
+    check_root(&root, &exe)?;
+    let boundary = match boundary {
+        Some(boundary) => {
+            let boundary = boundary.canonicalize()?;
+            if boundary == root || !root.starts_with(&boundary) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "boundary is not a parent of the install root",
+                ));
+            }
+            Some(boundary)
+        }
+        None => None,
+    };
+
+    #[cfg(unix)]
+    {
+        crate::unix::self_delete(&exe)?;
+    }
+    #[cfg(windows)]
+    {
+        crate::windows::self_delete(&exe, Some(&root))?;
+    }
+    fs::remove_dir_all(&root)?;
+
+    if let Some(boundary) = boundary {
+        let mut dir = root.parent();
+        while let Some(parent) = dir {
+            // removing a directory that still has contents fails, which is
+            // exactly what we want: only empty parents get cleaned up.
+            if parent == boundary || fs::remove_dir(parent).is_err() {
+                break;
+            }
+            dir = parent.parent();
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuses install roots which are very unlikely to be intended.
+fn check_root(root: &Path, exe: &Path) -> Result<(), io::Error> {
+    let refuse = |msg| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+    if root.parent().is_none() {
+        return refuse("refusing to delete the file system root");
+    }
+    if let Some(home) = home_dir() {
+        if home.starts_with(root) {
+            return refuse("refusing to delete the home directory");
+        }
+    }
+    if !exe.starts_with(root) {
+        return refuse("install root does not contain the executable");
+    }
+    Ok(())
+}
+
+fn home_dir() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let home = env::var_os("USERPROFILE");
+    #[cfg(not(windows))]
+    let home = env::var_os("HOME");
+    home.filter(|x| !x.is_empty())
+        .and_then(|x| PathBuf::from(x).canonicalize().ok())
+}
@@ -1,7 +1,22 @@
-use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::ErrorReport;
+use crate::fsutil::{current_exe, sibling_temp_path};
+use crate::hooks::{HookContext, Operation};
+use crate::options::{
+    DeleteOptions, DeleteOutcome, ReplaceOptions, ReplaceOutcome, StagingStrategy,
+};
 
 /// On Unix a running executable can be safely deleted.
 pub fn self_delete(exe: &Path) -> Result<(), io::Error> {
@@ -10,39 +25,586 @@ pub fn self_delete(exe: &Path) -> Result<(), io::Error> {
     Ok(())
 }
 
-pub fn self_replace(new_executable: &Path) -> Result<(), io::Error> {
-    let mut exe = env::current_exe()?;
-    if fs::symlink_metadata(&exe).map_or(false, |x| x.file_type().is_symlink()) {
-        exe = fs::read_link(exe)?;
+/// Deletes the executable, optionally deferring it until shutdown.
+pub fn self_delete_with(exe: &Path, options: &DeleteOptions) -> Result<DeleteOutcome, io::Error> {
+    let mut removed_links = Vec::new();
+    let mut remaining_hardlinks = Vec::new();
+    let mut to_delete = Vec::new();
+
+    if options.link_only && fs::symlink_metadata(exe)?.file_type().is_symlink() {
+        to_delete.push(exe.to_path_buf());
+    } else {
+        let exe = exe.canonicalize()?;
+        let has_hardlinks = fs::metadata(&exe)?.nlink() > 1;
+        if options.remove_links || has_hardlinks {
+            let mut dirs = sibling_dir(&exe).into_iter().collect::<Vec<_>>();
+            dirs.extend(options.search_dirs.iter().cloned());
+            if options.remove_links {
+                dirs.extend(crate::links::path_dirs());
+            }
+            let links = crate::links::find_links(&exe, &dirs)?;
+            if options.remove_links {
+                removed_links.extend(links.symlinks);
+                removed_links.extend(links.hardlinks);
+            } else {
+                remaining_hardlinks = links.hardlinks;
+            }
+        }
+        to_delete.push(exe);
+        to_delete.extend(removed_links.iter().cloned());
     }
-    let old_permissions = exe.metadata()?.permissions();
 
-    let prefix = if let Some(hint) = exe.file_stem().and_then(|x| x.to_str()) {
-        format!(".{hint}.__temp__")
+    let ctx = HookContext {
+        operation: Operation::Delete,
+        executable: exe,
+        new_executable: None,
+    };
+    crate::hooks::pre_commit(options.hooks.as_ref(), &ctx)?;
+
+    if options.deferred {
+        debug!(paths = ?to_delete, "scheduling deletion at shutdown");
+        crate::exit_hook::schedule_delete(&to_delete)?;
     } else {
-        ".__temp__".into()
+        for path in &to_delete {
+            debug!(path = %path.display(), "deleting");
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(DeleteOutcome {
+        deferred: options.deferred,
+        removed_links,
+        remaining_hardlinks,
+    })
+}
+
+pub fn self_replace(new_executable: &Path) -> Result<(), io::Error> {
+    self_replace_with(new_executable, &ReplaceOptions::default()).map(|_| ())
+}
+
+/// Replaces the executable.
+///
+/// This only fails if the executable was not replaced.  Errors after the commit
+/// are reported in the outcome.
+pub fn self_replace_with(
+    new_executable: &Path,
+    options: &ReplaceOptions,
+) -> Result<ReplaceOutcome, io::Error> {
+    // resolve all symlinks (which might be relative and chained, as it's the
+    // case with the launcher of a versioned install) to find the actual file.
+    let exe = current_exe()?;
+    let old_metadata = exe.metadata()?;
+    let old_permissions = old_metadata.permissions();
+    debug!(exe = %exe.display(), nlink = old_metadata.nlink(), "resolved executable");
+
+    // if the executable has more than one name, replacing it splits it off
+    // from the other names.  Find them so we can report them or fix them up.
+    let mut outcome = ReplaceOutcome::default();
+    if old_metadata.nlink() > 1 {
+        let mut dirs = sibling_dir(&exe).into_iter().collect::<Vec<_>>();
+        dirs.extend(options.search_dirs.iter().cloned());
+        outcome.hardlinks = crate::links::find_links(&exe.canonicalize()?, &dirs)?.hardlinks;
+        debug!(hardlinks = ?outcome.hardlinks, "found other hardlinks");
+    }
+
+    let applets = match options.applets {
+        Some(ref applets) => Some((
+            applets,
+            match options.applet_dir {
+                Some(ref dir) => dir.clone(),
+                None => get_directory_of(&exe)?.to_path_buf(),
+            },
+        )),
+        None => None,
     };
 
-    let tmp = tempfile::Builder::new()
-        .prefix(&prefix)
-        .tempfile_in(exe.parent().ok_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                "executable has no known parent folder",
-            )
-        })?)?;
-    fs::copy(new_executable, tmp.path())?;
-    fs::set_permissions(tmp.path(), old_permissions)?;
-
-    // if we made it this far, try to persist the temporary file and move it over.
-    let (_, path) = tmp.keep()?;
-    match fs::rename(&path, &exe) {
-        Ok(()) => {}
-        Err(err) => {
-            fs::remove_file(&path).ok();
+    let retry = options.retry.as_ref();
+    let monitor = options.monitor();
+    let ctx = HookContext {
+        operation: Operation::Replace,
+        executable: &exe,
+        new_executable: Some(new_executable),
+    };
+    let blocked;
+    if options.hardened {
+        let (staged, strategy) = crate::dirfd::StagedReplace::prepare(
+            &exe,
+            new_executable,
+            &old_metadata,
+            options,
+            &mut outcome.retries,
+        )?;
+        outcome.staging = strategy;
+        debug!(staging = ?strategy, "staged relative to the directory");
+        if let Err(err) = monitor
+            .check()
+            .and_then(|()| crate::hooks::pre_commit(options.hooks.as_ref(), &ctx))
+        {
+            staged.discard();
             return Err(err);
         }
+        blocked = block_signals_if(options.block_signals)?;
+        staged.commit(retry, &mut outcome.retries)?;
+        debug!("renamed the staged executable into place");
+    } else {
+        // if the source may be consumed and lives on the same file system, it's
+        // moved into place as is.  Otherwise on linux the new executable is written
+        // to an anonymous file which is only given a name at commit time, so
+        // nothing is left behind if we crash while copying.  Everywhere else (or if
+        // not supported) a named temporary is used.
+        let dir = get_directory_of(&exe)?;
+        let new_metadata = fs::metadata(new_executable)?;
+        let linked = if options.consume_source && new_metadata.dev() == old_metadata.dev() {
+            // the same device does not guarantee that the source can be moved (bind
+            // mounts cannot be crossed for instance), so it's linked next to the
+            // executable right away and copied instead if that fails.
+            let link = link_source(new_executable, &exe);
+            debug!(error = ?link.as_ref().err(), "linked the source");
+            link.ok()
+        } else {
+            None
+        };
+        if linked.is_none() {
+            crate::copy::check_free_space(dir, new_metadata.len())?;
+        }
+        let staged = if let Some(link) = linked {
+            outcome.staging = StagingStrategy::Rename;
+            debug!(path = %link.path().display(), "consuming the source as the staged executable");
+            Staged::Source(link)
+        } else {
+            let mut staged = match open_anonymous(dir)? {
+                Some(staged) => staged,
+                None => {
+                    let prefix = if let Some(hint) = exe.file_stem().and_then(|x| x.to_str()) {
+                        format!(".{hint}.__temp__")
+                    } else {
+                        ".__temp__".into()
+                    };
+                    let tmp = tempfile::Builder::new().prefix(&prefix).tempfile_in(dir)?;
+                    debug!(path = %tmp.path().display(), "staging to a named temporary");
+                    Staged::Named(tmp)
+                }
+            };
+            if let Some(file) = staged.file_mut() {
+                outcome.staging = crate::retry::retry(retry, &mut outcome.retries, || {
+                    crate::copy::copy_contents(new_executable, file, &monitor)
+                })?;
+                debug!(
+                    staging = ?outcome.staging,
+                    bytes = new_metadata.len(),
+                    "copied the new executable"
+                );
+                file.set_permissions(old_permissions.clone())?;
+                debug!(
+                    mode = format_args!("{:o}", old_metadata.mode() & 0o7777),
+                    "restored permissions"
+                );
+                // make sure that we never commit a truncated or corrupted executable.
+                crate::copy::verify_contents(new_executable, file, options.verify, &monitor)?;
+            }
+            staged
+        };
+
+        // last chance to back out, after this the replacement is committed.
+        monitor.check()?;
+        crate::hooks::pre_commit(options.hooks.as_ref(), &ctx)?;
+
+        // from here on an interruption would leave files behind, so termination
+        // signals are held back until the commit is done if requested.
+        blocked = block_signals_if(options.block_signals)?;
+
+        // if we made it this far, try to persist the temporary file and move it over.
+        let path = match staged {
+            #[cfg(target_os = "linux")]
+            Staged::Anonymous(file) => link_anonymous(&file, &exe)?,
+            Staged::Named(tmp) => tmp.keep()?.1,
+            Staged::Source(link) => {
+                // the permissions are shared with the source, so they are only
+                // changed once the replacement can no longer be vetoed.
+                fs::set_permissions(link.path(), old_permissions)?;
+                link.keep()
+            }
+        };
+        debug!(from = %path.display(), to = %exe.display(), "renaming into place");
+        let rv = match crate::retry::retry(retry, &mut outcome.retries, || fs::rename(&path, &exe))
+        {
+            Err(err) if options.in_place_fallback && is_rename_unsupported(&err) => {
+                debug!(error = %err, "rename unsupported, writing in place");
+                commit_in_place(&path, &exe, &mut outcome)
+            }
+            other => other,
+        };
+        if rv.is_err() || outcome.in_place {
+            debug!(path = %path.display(), "removing the staged executable");
+            fs::remove_file(&path).ok();
+        }
+        if outcome.staging == StagingStrategy::Rename {
+            if rv.is_err() {
+                fs::set_permissions(new_executable, new_metadata.permissions()).ok();
+            } else {
+                debug!(path = %new_executable.display(), "removing the consumed source");
+                fs::remove_file(new_executable).ok();
+            }
+        }
+        rv?;
+    }
+
+    // the new executable is in place, so errors from here on do not fail the
+    // replacement but are reported in the outcome.
+    if options.update_hardlinks {
+        for link in &outcome.hardlinks {
+            debug!(link = %link.display(), "updating hardlink");
+            if let Err(err) = relink(&exe, link) {
+                debug!(link = %link.display(), error = %err, "failed to update hardlink");
+                outcome
+                    .hardlink_errors
+                    .push((link.clone(), ErrorReport::from(&err)));
+            }
+        }
+        outcome.hardlinks_updated = outcome.hardlink_errors.is_empty();
+    }
+    drop(blocked);
+
+    if let Some((applets, dir)) = applets {
+        let rv = crate::links::reconcile_applets(
+            &exe,
+            &dir,
+            applets,
+            &mut outcome.applets_added,
+            &mut outcome.applets_removed,
+        );
+        debug!(
+            added = ?outcome.applets_added,
+            removed = ?outcome.applets_removed,
+            result = ?rv,
+            "reconciled applets"
+        );
+        outcome.applet_error = rv.err().map(|err| ErrorReport::from(&err));
+    }
+
+    Ok(outcome)
+}
+
+/// Stages the new executable now and moves it into place at shutdown.
+pub fn self_replace_on_exit(new_executable: &Path) -> Result<(), io::Error> {
+    let exe = current_exe()?;
+    let md = fs::metadata(new_executable)?;
+    if !md.is_file() || md.len() == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "new executable is not a non-empty file",
+        ));
+    }
+
+    let staged = sibling_temp_path(&exe, "__staged__")?;
+    debug!(path = %staged.display(), "staging for a replacement at shutdown");
+    let rv = (|| {
+        fs::copy(new_executable, &staged)?;
+        fs::set_permissions(&staged, exe.metadata()?.permissions())?;
+        crate::exit_hook::schedule_rename(&staged, &exe)
+    })();
+    match rv {
+        Ok(previous) => {
+            if let Some(previous) = previous {
+                fs::remove_file(previous).ok();
+            }
+            Ok(())
+        }
+        Err(err) => {
+            fs::remove_file(&staged).ok();
+            Err(err)
+        }
+    }
+}
+
+/// Revokes a replacement scheduled with [`self_replace_on_exit`].
+pub fn cancel_self_replace() -> bool {
+    match crate::exit_hook::cancel_rename() {
+        Some(staged) => {
+            fs::remove_file(staged).ok();
+            true
+        }
+        None => false,
     }
+}
+
+/// Checks if a rename failed because it's impossible for this file.
+///
+/// This is the case for bind-mounted files (as common in containers) and some
+/// FUSE file systems that do not support renames.
+fn is_rename_unsupported(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EBUSY) | Some(libc::EXDEV) | Some(libc::ENOSYS) | Some(libc::EOPNOTSUPP)
+    )
+}
 
+/// Writes the staged executable over the contents of the old one.
+///
+/// A running executable cannot be opened for writing, in which case a helper
+/// does it once the process exited.
+fn commit_in_place(
+    staged: &Path,
+    exe: &Path,
+    outcome: &mut ReplaceOutcome,
+) -> Result<(), io::Error> {
+    match crate::fsutil::write_in_place(staged, exe) {
+        Ok(()) => {}
+        Err(err) if err.raw_os_error() == Some(libc::ETXTBSY) => {
+            debug!("executable is busy, leaving the write to a helper");
+            crate::helper::UpdateHelper::new(staged)
+                .in_place(true)
+                .spawn()?;
+            outcome.deferred = true;
+        }
+        Err(err) => return Err(err),
+    }
+    outcome.in_place = true;
+    Ok(())
+}
+
+/// The new executable before it's moved into place.
+enum Staged {
+    #[cfg(target_os = "linux")]
+    Anonymous(fs::File),
+    Named(tempfile::NamedTempFile),
+    Source(SourceLink),
+}
+
+impl Staged {
+    fn file_mut(&mut self) -> Option<&mut fs::File> {
+        match *self {
+            #[cfg(target_os = "linux")]
+            Staged::Anonymous(ref mut file) => Some(file),
+            Staged::Named(ref mut tmp) => Some(tmp.as_file_mut()),
+            Staged::Source(_) => None,
+        }
+    }
+}
+
+/// A hardlink to the source next to the executable, removed again unless kept.
+struct SourceLink(Option<PathBuf>);
+
+impl SourceLink {
+    fn path(&self) -> &Path {
+        self.0.as_deref().unwrap()
+    }
+
+    fn keep(mut self) -> PathBuf {
+        self.0.take().unwrap()
+    }
+}
+
+impl Drop for SourceLink {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.0 {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// Links the source of a replacement next to the executable.
+fn link_source(source: &Path, exe: &Path) -> Result<SourceLink, io::Error> {
+    let path = sibling_temp_path(exe, "__temp__")?;
+    fs::hard_link(source, &path)?;
+    Ok(SourceLink(Some(path)))
+}
+
+/// Opens an unnamed `O_TMPFILE` inode in `dir`.
+#[cfg(target_os = "linux")]
+fn open_anonymous(dir: &Path) -> Result<Option<Staged>, io::Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+    Ok(
+        crate::dirfd::open_anonymous(libc::AT_FDCWD, &dir)?.map(|file| {
+            debug!("staging to an anonymous file");
+            Staged::Anonymous(file)
+        }),
+    )
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_anonymous(_dir: &Path) -> Result<Option<Staged>, io::Error> {
+    Ok(None)
+}
+
+/// Gives an anonymous file a temporary name next to `exe`.
+#[cfg(target_os = "linux")]
+fn link_anonymous(file: &fs::File, exe: &Path) -> Result<PathBuf, io::Error> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = sibling_temp_path(exe, "__temp__")?;
+    let target = CString::new(path.as_os_str().as_bytes())?;
+    crate::dirfd::link_anonymous(file, libc::AT_FDCWD, &target)?;
+    debug!(path = %path.display(), "linked the anonymous file");
+    Ok(path)
+}
+
+fn block_signals_if(yes: bool) -> Result<Option<BlockedSignals>, io::Error> {
+    if yes {
+        BlockedSignals::block().map(Some)
+    } else {
+        Ok(None)
+    }
+}
+
+/// The signals that arrived while [`BlockedSignals`] was active, one bit per signal.
+static RECEIVED_SIGNALS: AtomicUsize = AtomicUsize::new(0);
+
+/// Holds back `SIGINT`, `SIGTERM` and `SIGHUP` for the whole process until dropped.
+///
+/// A signal mask only applies to the calling thread, so instead a handler is
+/// installed for the duration which merely records the signal.  Once dropped the
+/// previous dispositions are restored and the recorded signals are raised again,
+/// so they are handled the way they would have been without us.
+struct BlockedSignals {
+    previous: Vec<(i32, libc::sigaction)>,
+}
+
+impl BlockedSignals {
+    fn block() -> Result<BlockedSignals, io::Error> {
+        RECEIVED_SIGNALS.store(0, Ordering::SeqCst);
+        let mut rv = BlockedSignals {
+            previous: Vec::with_capacity(3),
+        };
+        for &sig in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            unsafe {
+                let mut previous: libc::sigaction = mem::zeroed();
+                if libc::sigaction(sig, ptr::null(), &mut previous) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // ignored signals never terminate the process
+                if previous.sa_sigaction == libc::SIG_IGN {
+                    continue;
+                }
+                let mut action: libc::sigaction = mem::zeroed();
+                action.sa_sigaction = record_signal as *const () as usize;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                if libc::sigaction(sig, &action, ptr::null_mut()) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                rv.previous.push((sig, previous));
+            }
+        }
+        Ok(rv)
+    }
+}
+
+impl Drop for BlockedSignals {
+    fn drop(&mut self) {
+        for (sig, previous) in self.previous.drain(..) {
+            unsafe {
+                libc::sigaction(sig, &previous, ptr::null_mut());
+            }
+        }
+        let received = RECEIVED_SIGNALS.swap(0, Ordering::SeqCst);
+        for &sig in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            if received & (1 << sig) != 0 {
+                debug!(
+                    signal = sig,
+                    "delivering signal held back during the commit"
+                );
+                unsafe {
+                    libc::raise(sig);
+                }
+            }
+        }
+    }
+}
+
+extern "C" fn record_signal(sig: i32) {
+    RECEIVED_SIGNALS.fetch_or(1 << sig, Ordering::SeqCst);
+}
+
+/// Atomically points the hardlink at `link` to `target`.
+fn relink(target: &Path, link: &Path) -> Result<(), io::Error> {
+    let tmp = sibling_temp_path(link, "__temp__")?;
+    fs::hard_link(target, &tmp)?;
+    if let Err(err) = fs::rename(&tmp, link) {
+        fs::remove_file(&tmp).ok();
+        return Err(err);
+    }
+    Ok(())
+}
+
+fn get_directory_of(p: &Path) -> Result<&Path, io::Error> {
+    p.parent().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Other,
+            "executable has no known parent folder",
+        )
+    })
+}
+
+fn sibling_dir(path: &Path) -> Option<PathBuf> {
+    path.parent().map(|x| x.to_path_buf())
+}
+
+/// Returns the token by which an update helper identifies its parent.
+pub fn parent_token() -> Result<OsString, io::Error> {
+    Ok(std::process::id().to_string().into())
+}
+
+/// Spawns a process in a new session so it outlives us.
+pub fn spawn_detached(cmd: &mut Command) -> Result<(), io::Error> {
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+/// Blocks until the parent process identified by the token shut down.
+///
+/// On Linux this uses a pidfd if available.  Everywhere else (and as fallback)
+/// we poll until we got reparented, which is what happens once the parent is gone.
+pub fn wait_for_parent(token: &OsStr) -> Result<(), io::Error> {
+    let pid: libc::pid_t = token
+        .to_str()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid parent pid"))?;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) } as i32;
+        if fd >= 0 {
+            // the pid could have been recycled between our parent's shutdown and
+            // opening the pidfd.  If we are still parented by it, it's the right one.
+            let mut pfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let rv = loop {
+                if unsafe { libc::getppid() } != pid {
+                    break Ok(());
+                }
+                if unsafe { libc::poll(&mut pfd, 1, -1) } >= 0 {
+                    break Ok(());
+                }
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    break Err(err);
+                }
+            };
+            unsafe { libc::close(fd) };
+            return rv;
+        }
+    }
+
+    while unsafe { libc::getppid() } == pid {
+        thread::sleep(Duration::from_millis(50));
+    }
     Ok(())
 }
@@ -0,0 +1,223 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::fsutil::{replace_symlink, sibling_temp_path};
+
+/// Manages an A/B style installation with one folder per version.
+///
+/// Instead of overwriting the executable in place, every version is installed into
+/// its own folder and a `current` symlink selects the active one:
+///
+/// ```text
+/// <root>/versions/1.0.0/tool
+/// <root>/versions/1.1.0/tool
+/// <root>/current -> versions/1.1.0
+/// <root>/previous -> versions/1.0.0
+/// ~/.local/bin/tool -> <root>/current/tool
+/// ```
+///
+/// Switching versions is an atomic replacement of the `current` symlink, which
+/// makes rolling back trivial.  The launcher symlink (for instance on the `PATH`)
+/// is created once with [`link_launcher`](Self::link_launcher) and never needs to
+/// change.
+///
+/// ```
+/// # fn foo() -> Result<(), std::io::Error> {
+/// use self_replace::VersionedInstall;
+/// let install = VersionedInstall::new("/opt/tool", "tool");
+/// install.update("1.1.0", "/tmp/update/tool")?;
+/// install.prune(3)?;
+/// # Ok(()) }
+/// ```
+///
+/// This is only supported on Unix.
+#[derive(Debug, Clone)]
+pub struct VersionedInstall {
+    root: PathBuf,
+    name: OsString,
+}
+
+impl VersionedInstall {
+    /// Creates a layout in `root` for an executable with the given file name.
+    pub fn new<P: AsRef<Path>, S: AsRef<OsStr>>(root: P, name: S) -> VersionedInstall {
+        VersionedInstall {
+            root: root.as_ref().to_path_buf(),
+            name: name.as_ref().to_os_string(),
+        }
+    }
+
+    /// Returns the path of the executable of the given version.
+    pub fn executable(&self, version: &str) -> PathBuf {
+        self.versions_dir().join(version).join(&self.name)
+    }
+
+    /// Installs the executable as a new version without activating it.
+    ///
+    /// The version is written to a temporary folder first and then moved into
+    /// place, so a version folder is either complete or does not exist.
+    pub fn install<P: AsRef<Path>>(
+        &self,
+        version: &str,
+        executable: P,
+    ) -> Result<PathBuf, io::Error> {
+        check_version(version)?;
+        let versions = self.versions_dir();
+        fs::create_dir_all(&versions)?;
+        let target = versions.join(version);
+        if fs::symlink_metadata(&target).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("version {version} is already installed"),
+            ));
+        }
+
+        let staged = sibling_temp_path(&target, "__staged__")?;
+        let rv = (|| {
+            fs::create_dir(&staged)?;
+            fs::copy(executable.as_ref(), staged.join(&self.name))?;
+            fs::rename(&staged, &target)
+        })();
+        if let Err(err) = rv {
+            fs::remove_dir_all(&staged).ok();
+            return Err(err);
+        }
+        Ok(target.join(&self.name))
+    }
+
+    /// Makes the given version the current one.
+    ///
+    /// The previously active version is remembered for [`rollback`](Self::rollback).
+    pub fn activate(&self, version: &str) -> Result<(), io::Error> {
+        check_version(version)?;
+        if !self.executable(version).is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("version {version} is not installed"),
+            ));
+        }
+        let current = self.current_version()?;
+        if current.as_deref() == Some(version) {
+            return Ok(());
+        }
+        if let Some(current) = current {
+            replace_symlink(&self.previous_link(), &version_link_target(&current))?;
+        }
+        replace_symlink(&self.current_link(), &version_link_target(version))
+    }
+
+    /// Installs and activates a new version in one go.
+    pub fn update<P: AsRef<Path>>(
+        &self,
+        version: &str,
+        executable: P,
+    ) -> Result<PathBuf, io::Error> {
+        let rv = self.install(version, executable)?;
+        self.activate(version)?;
+        Ok(rv)
+    }
+
+    /// Switches back to the previously active version and returns it.
+    pub fn rollback(&self) -> Result<String, io::Error> {
+        let previous = read_version_link(&self.previous_link())?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no previous version to roll back to",
+            )
+        })?;
+        self.activate(&previous)?;
+        Ok(previous)
+    }
+
+    /// Returns the currently active version.
+    pub fn current_version(&self) -> Result<Option<String>, io::Error> {
+        read_version_link(&self.current_link())
+    }
+
+    /// Returns all installed versions, newest installation first.
+    pub fn versions(&self) -> Result<Vec<String>, io::Error> {
+        let mut rv = Vec::new();
+        let entries = match fs::read_dir(self.versions_dir()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let name = match entry.file_name().into_string() {
+                Ok(name) if !name.starts_with('.') => name,
+                _ => continue,
+            };
+            let modified = entry
+                .metadata()?
+                .modified()
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            rv.push((modified, name));
+        }
+        rv.sort_by(|a, b| b.cmp(a));
+        Ok(rv.into_iter().map(|(_, name)| name).collect())
+    }
+
+    /// Removes all but the `keep` most recently installed versions.
+    ///
+    /// The current and the previous version are never removed.  Returns the
+    /// versions that were removed.
+    pub fn prune(&self, keep: usize) -> Result<Vec<String>, io::Error> {
+        let current = self.current_version()?;
+        let previous = read_version_link(&self.previous_link())?;
+        let mut removed = Vec::new();
+        for version in self.versions()?.into_iter().skip(keep) {
+            if Some(&version) == current.as_ref() || Some(&version) == previous.as_ref() {
+                continue;
+            }
+            fs::remove_dir_all(self.versions_dir().join(&version))?;
+            removed.push(version);
+        }
+        Ok(removed)
+    }
+
+    /// Points a launcher symlink (for instance on the `PATH`) to the current version.
+    pub fn link_launcher<P: AsRef<Path>>(&self, launcher: P) -> Result<(), io::Error> {
+        replace_symlink(launcher.as_ref(), &self.current_link().join(&self.name))
+    }
+
+    fn versions_dir(&self) -> PathBuf {
+        self.root.join("versions")
+    }
+
+    fn current_link(&self) -> PathBuf {
+        self.root.join("current")
+    }
+
+    fn previous_link(&self) -> PathBuf {
+        self.root.join("previous")
+    }
+}
+
+// links are relative so that the whole installation can be moved.
+fn version_link_target(version: &str) -> PathBuf {
+    Path::new("versions").join(version)
+}
+
+fn read_version_link(link: &Path) -> Result<Option<String>, io::Error> {
+    match fs::read_link(link) {
+        Ok(target) => Ok(target
+            .file_name()
+            .and_then(|x| x.to_str())
+            .map(|x| x.to_string())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn check_version(version: &str) -> Result<(), io::Error> {
+    if version.is_empty() || version.starts_with('.') || version.contains(['/', '\\']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid version {version:?}"),
+        ));
+    }
+    Ok(())
+}
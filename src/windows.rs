@@ -1,10 +1,12 @@
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::io;
 use std::mem;
 use std::os::windows::prelude::OsStrExt;
+use std::os::windows::process::CommandExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::ptr;
 use std::thread;
 use std::time::Duration;
@@ -21,8 +23,8 @@ use windows_sys::Win32::Storage::FileSystem::{
 use windows_sys::Win32::System::Environment::GetCommandLineW;
 use windows_sys::Win32::System::LibraryLoader::GetModuleFileNameW;
 use windows_sys::Win32::System::Threading::{
-    CreateProcessA, ExitProcess, GetCurrentProcess, WaitForSingleObject, CREATE_NO_WINDOW,
-    INFINITE, PROCESS_INFORMATION, STARTUPINFOA,
+    CreateProcessA, ExitProcess, GetCurrentProcess, WaitForSingleObject, CREATE_NEW_PROCESS_GROUP,
+    CREATE_NO_WINDOW, DETACHED_PROCESS, INFINITE, PROCESS_INFORMATION, STARTUPINFOA,
 };
 use windows_sys::Win32::UI::Shell::CommandLineToArgvW;
 
@@ -49,6 +51,11 @@ fn spawn_tmp_exe_to_delete_parent(
     tmp_exe: PathBuf,
     original_exe: PathBuf,
 ) -> Result<(), io::Error> {
+    debug!(
+        helper = %tmp_exe.display(),
+        exe = %original_exe.display(),
+        "spawning helper to delete the executable at shutdown"
+    );
     let tmp_exe_win: Vec<_> = tmp_exe.as_os_str().encode_wide().chain(Some(0)).collect();
     let sa = SECURITY_ATTRIBUTES {
         nLength: mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
@@ -210,6 +217,7 @@ fn schedule_self_deletion_on_shutdown(
     let first_choice = env::temp_dir();
     let relocated_exe = get_temp_executable_name(&first_choice, RELOCATED_SUFFIX);
     if fs::rename(exe, &relocated_exe).is_ok() {
+        debug!(path = %relocated_exe.display(), "relocated executable to the temp dir");
         let tmp_exe = get_temp_executable_name(&first_choice, SELFDELETE_SUFFIX);
         fs::copy(&relocated_exe, &tmp_exe)?;
         spawn_tmp_exe_to_delete_parent(tmp_exe, relocated_exe)?;
@@ -280,12 +288,62 @@ pub fn self_delete(exe: &Path, protected_path: Option<&Path>) -> Result<(), io::
 /// This is similar to self_delete, but first renames the executable to a new temporary
 /// location so that the executable can be updated by the given other one.
 pub fn self_replace(new_executable: &Path) -> Result<(), io::Error> {
-    let exe = env::current_exe()?.canonicalize()?;
+    let exe = crate::fsutil::current_exe()?;
     let old_exe = get_temp_executable_name(get_directory_of(&exe)?, RELOCATED_SUFFIX);
+    debug!(exe = %exe.display(), to = %old_exe.display(), "relocating executable");
     fs::rename(&exe, &old_exe)?;
     schedule_self_deletion_on_shutdown(&old_exe, None)?;
     let temp_exe = get_temp_executable_name(get_directory_of(&exe)?, TEMP_SUFFIX);
     fs::copy(new_executable, &temp_exe)?;
+    debug!(path = %temp_exe.display(), "copied the new executable");
     fs::rename(&temp_exe, &exe)?;
+    debug!("renamed the new executable into place");
+    Ok(())
+}
+
+/// Returns the token by which an update helper identifies its parent.
+///
+/// This is an inheritable handle to the current process.  It is intentionally
+/// never closed as it needs to stay valid until the helper picked it up.
+pub fn parent_token() -> Result<OsString, io::Error> {
+    let mut process_handle = 0;
+    unsafe {
+        if DuplicateHandle(
+            GetCurrentProcess(),
+            GetCurrentProcess(),
+            GetCurrentProcess(),
+            &mut process_handle,
+            0,
+            1,
+            DUPLICATE_SAME_ACCESS,
+        ) == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(process_handle.to_string().into())
+}
+
+/// Spawns a process detached from our console so it outlives us.
+pub fn spawn_detached(cmd: &mut Command) -> Result<(), io::Error> {
+    cmd.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+/// Blocks until the parent process identified by the token shut down.
+pub fn wait_for_parent(token: &OsStr) -> Result<(), io::Error> {
+    let handle: HANDLE = token
+        .to_str()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid parent handle"))?;
+    let rv = unsafe { WaitForSingleObject(handle, INFINITE) };
+    unsafe { CloseHandle(handle) };
+    if rv != WAIT_OBJECT_0 {
+        return Err(io::Error::last_os_error());
+    }
     Ok(())
 }
@@ -0,0 +1,72 @@
+#![cfg(feature = "audit")]
+use std::fs;
+use std::time::{Duration, UNIX_EPOCH};
+
+use self_replace::{history, AuditEntry, AuditOperation};
+
+#[test]
+fn test_history() {
+    let workspace = tempfile::tempdir().unwrap();
+    let log = workspace.path().join("audit.jsonl");
+    assert!(history(&log).unwrap().is_empty());
+
+    fs::write(
+        &log,
+        concat!(
+            r#"{"timestamp":1700000000000,"operation":"replace","executable":"/opt/tool","old_sha256":"aa","new_sha256":"bb","old_version":"1.0","new_version":"1.1","user":"ops","outcome":"ok","error":null}"#,
+            "\n",
+            r#"{ "timestamp": 1700000001000, "operation": "rollback", "executable": "/opt/tool",
+"#,
+        ),
+    )
+    .unwrap();
+    assert!(history(&log).is_err());
+
+    fs::write(
+        &log,
+        concat!(
+            r#"{"timestamp":1700000000000,"operation":"replace","executable":"/opt/tool","old_sha256":"aa","new_sha256":"bb","old_version":"1.0","new_version":"1.1","user":"ops","outcome":"ok","error":null}"#,
+            "\n\n",
+            r#"{ "timestamp": 1700000001000, "operation": "delete", "executable": "C:\\tool \"x\" \u00e9\ud83d\ude00", "future": true, "error": "denied\n" }"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+    let entries = history(&log).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].operation, AuditOperation::Replace);
+    assert_eq!(
+        entries[0].timestamp,
+        UNIX_EPOCH + Duration::from_millis(1700000000000)
+    );
+    assert_eq!(entries[0].new_version.as_deref(), Some("1.1"));
+    assert_eq!(entries[0].user.as_deref(), Some("ops"));
+    assert!(entries[0].succeeded());
+    assert_eq!(entries[1].operation, AuditOperation::Delete);
+    assert_eq!(
+        entries[1].executable.to_str(),
+        Some("C:\\tool \"x\" \u{e9}\u{1f600}")
+    );
+    assert_eq!(entries[1].error.as_deref(), Some("denied\n"));
+    assert_eq!(entries[1].old_sha256, None);
+}
+
+#[test]
+fn test_serialize_history() {
+    let workspace = tempfile::tempdir().unwrap();
+    let log = workspace.path().join("audit.jsonl");
+    fs::write(
+        &log,
+        r#"{"timestamp":1700000000000,"operation":"rollback","executable":"/opt/tool","error":null}"#,
+    )
+    .unwrap();
+    let value = serde_json::to_value(history(&log).unwrap()).unwrap();
+    assert_eq!(value[0]["operation"], "rollback");
+    assert_eq!(value[0]["executable"], "/opt/tool");
+    assert_eq!(value[0]["timestamp"], 1700000000000u64);
+    let entry: AuditEntry = serde_json::from_value(value[0].clone()).unwrap();
+    assert_eq!(
+        entry.timestamp,
+        UNIX_EPOCH + Duration::from_millis(1700000000000)
+    );
+}
@@ -0,0 +1,153 @@
+use std::fs;
+
+use self_replace::Bundle;
+
+#[test]
+fn test_bundle_commit() {
+    let workspace = tempfile::tempdir().unwrap();
+    let staging = tempfile::tempdir().unwrap();
+    let a = workspace.path().join("a.txt");
+    let b = workspace.path().join("b.txt");
+    fs::write(&a, "old a").unwrap();
+    fs::write(staging.path().join("a.txt"), "new a").unwrap();
+    fs::write(staging.path().join("b.txt"), "new b").unwrap();
+
+    Bundle::new()
+        .file(&a, staging.path().join("a.txt"))
+        .file(&b, staging.path().join("b.txt"))
+        .commit()
+        .unwrap();
+
+    assert_eq!(fs::read_to_string(&a).unwrap(), "new a");
+    assert_eq!(fs::read_to_string(&b).unwrap(), "new b");
+    assert_eq!(workspace.path().read_dir().unwrap().count(), 2);
+}
+
+#[test]
+fn test_bundle_failed_staging() {
+    let workspace = tempfile::tempdir().unwrap();
+    let staging = tempfile::tempdir().unwrap();
+    let a = workspace.path().join("a.txt");
+    let b = workspace.path().join("b.txt");
+    fs::write(&a, "old a").unwrap();
+    fs::write(&b, "old b").unwrap();
+    fs::write(staging.path().join("a.txt"), "new a").unwrap();
+
+    assert!(Bundle::new()
+        .file(&a, staging.path().join("a.txt"))
+        .file(&b, staging.path().join("missing.txt"))
+        .commit()
+        .is_err());
+
+    assert_eq!(fs::read_to_string(&a).unwrap(), "old a");
+    assert_eq!(fs::read_to_string(&b).unwrap(), "old b");
+    assert_eq!(workspace.path().read_dir().unwrap().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_bundle_commit_versioned() {
+    use std::env;
+    use std::path::{Path, PathBuf};
+
+    let workspace = tempfile::tempdir().unwrap();
+    let staging = tempfile::tempdir().unwrap();
+    let current = workspace.path().join("current");
+    fs::write(staging.path().join("tool"), "tool 2").unwrap();
+    fs::write(staging.path().join("plugin.so"), "plugin 2").unwrap();
+
+    for version in ["1", "2"] {
+        Bundle::new()
+            .file("bin/tool", staging.path().join("tool"))
+            .file("lib/plugin.so", staging.path().join("plugin.so"))
+            .commit_versioned(workspace.path().join("versions").join(version), &current)
+            .unwrap();
+        assert_eq!(
+            fs::read_link(&current).unwrap(),
+            Path::new("versions").join(version)
+        );
+    }
+    assert_eq!(
+        fs::read_to_string(current.join("lib/plugin.so")).unwrap(),
+        "plugin 2"
+    );
+
+    // a bundle that fails to write leaves the link alone
+    assert!(Bundle::new()
+        .file("bin/tool", staging.path().join("missing"))
+        .commit_versioned(workspace.path().join("versions").join("3"), &current)
+        .is_err());
+    assert!(!workspace.path().join("versions").join("3").exists());
+    assert_eq!(
+        fs::read_link(&current).unwrap(),
+        Path::new("versions").join("2")
+    );
+    assert_eq!(workspace.path().read_dir().unwrap().count(), 2);
+
+    // a relative version folder is resolved against the working directory, not
+    // against the folder of the link
+    let mut relative = PathBuf::new();
+    for _ in env::current_dir().unwrap().components().skip(1) {
+        relative.push("..");
+    }
+    relative.push(workspace.path().strip_prefix("/").unwrap());
+    Bundle::new()
+        .file("lib/plugin.so", staging.path().join("plugin.so"))
+        .commit_versioned(relative.join("versions").join("4"), &current)
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(current.join("lib/plugin.so")).unwrap(),
+        "plugin 2"
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_bundle_failed_commit() {
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+
+    struct Unmount<'a>(&'a Path);
+    impl Drop for Unmount<'_> {
+        fn drop(&mut self) {
+            Command::new("umount").arg(self.0).status().ok();
+        }
+    }
+
+    let workspace = tempfile::tempdir().unwrap();
+    let staging = tempfile::tempdir().unwrap();
+    let a = workspace.path().join("a.txt");
+    let b = workspace.path().join("b.txt");
+    let c = workspace.path().join("c.txt");
+    fs::write(&a, "old a").unwrap();
+    fs::write(&c, "old c").unwrap();
+    for name in ["a.txt", "b.txt", "c.txt"] {
+        fs::write(staging.path().join(name), format!("new {name}")).unwrap();
+    }
+
+    // a bind-mounted file cannot be renamed, so the last file fails to commit
+    // after the others went through.  This requires privileges, so the test is
+    // skipped without.
+    let mounted = Command::new("mount")
+        .arg("--bind")
+        .arg(staging.path().join("c.txt"))
+        .arg(&c)
+        .stderr(Stdio::null())
+        .status()
+        .map_or(false, |x| x.success());
+    if !mounted {
+        return;
+    }
+    let _unmount = Unmount(&c);
+
+    assert!(Bundle::new()
+        .file(&a, staging.path().join("a.txt"))
+        .file(&b, staging.path().join("b.txt"))
+        .file(&c, staging.path().join("c.txt"))
+        .commit()
+        .is_err());
+
+    assert_eq!(fs::read_to_string(&a).unwrap(), "old a");
+    assert!(!b.exists());
+    assert_eq!(workspace.path().read_dir().unwrap().count(), 2);
+}
@@ -1,7 +1,8 @@
 use std::env::consts::EXE_EXTENSION;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::{env, fs};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
 
 fn compile_example(name: &str) {
     let mut cmd = Command::new("cargo");
@@ -9,6 +10,11 @@ fn compile_example(name: &str) {
         .arg("build")
         .arg("--example")
         .arg(name)
+        .args(if cfg!(feature = "audit") {
+            &["--features", "audit"][..]
+        } else {
+            &[]
+        })
         .output()
         .unwrap();
     println!("stdout:\n{}", String::from_utf8_lossy(&output.stdout));
@@ -71,6 +77,14 @@ fn run(opts: RunOptions) {
     assert_eq!(stdout.trim(), opts.expected_output);
 }
 
+fn wait_until<F: FnMut() -> bool>(mut f: F) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !f() {
+        assert!(Instant::now() < deadline, "timed out waiting");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
 #[test]
 fn test_self_delete() {
     let workspace = tempfile::tempdir().unwrap();
@@ -105,6 +119,40 @@ fn test_self_delete_force_exit() {
     assert!(scratchspace.path().read_dir().unwrap().next().is_none());
 }
 
+#[test]
+fn test_self_delete_deferred() {
+    let workspace = tempfile::tempdir().unwrap();
+    let scratchspace = tempfile::tempdir().unwrap();
+    compile_example("deletes-itself-on-exit");
+    let exe = get_executable("deletes-itself-on-exit", workspace.path());
+    assert!(exe.is_file());
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "When I exit, I am deleted",
+    });
+    assert!(!exe.is_file());
+    assert!(scratchspace.path().read_dir().unwrap().next().is_none());
+}
+
+#[test]
+fn test_self_delete_deferred_force_exit() {
+    let workspace = tempfile::tempdir().unwrap();
+    let scratchspace = tempfile::tempdir().unwrap();
+    compile_example("deletes-itself-on-exit");
+    let exe = get_executable("deletes-itself-on-exit", workspace.path());
+    assert!(exe.is_file());
+    run(RunOptions {
+        path: &exe,
+        force_exit: true,
+        scratchspace: scratchspace.path(),
+        expected_output: "When I exit, I am deleted",
+    });
+    assert!(!exe.is_file());
+    assert!(scratchspace.path().read_dir().unwrap().next().is_none());
+}
+
 #[test]
 fn test_self_delete_outside_path() {
     let scratchspace = tempfile::tempdir().unwrap();
@@ -143,6 +191,126 @@ fn test_self_delete_outside_path_force_exit() {
     assert!(scratchspace.path().read_dir().unwrap().next().is_none());
 }
 
+#[test]
+fn test_self_delete_tree() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = tempfile::tempdir().unwrap();
+    let prefix = workspace.path().join("prefix");
+    let root = prefix.join("app");
+    let bin = root.join("bin");
+    fs::create_dir_all(&bin).unwrap();
+    fs::write(root.join("data.txt"), "data").unwrap();
+    compile_example("deletes-itself-tree");
+    let exe = get_executable("deletes-itself-tree", &bin);
+    assert!(exe.is_file());
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "When I finish, my install root and its empty parent are gone.",
+    });
+    assert!(!exe.is_file());
+    assert!(!root.is_dir());
+    assert!(!prefix.is_dir());
+    assert!(workspace.path().is_dir());
+    assert!(scratchspace.path().read_dir().unwrap().next().is_none());
+}
+
+#[test]
+fn test_self_uninstall() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = tempfile::tempdir().unwrap();
+    let bin = workspace.path().join("bin");
+    let share = workspace.path().join("share");
+    fs::create_dir_all(&bin).unwrap();
+    fs::create_dir_all(share.join("data")).unwrap();
+    fs::write(share.join("data").join("stuff.txt"), "stuff").unwrap();
+    fs::write(share.join("completions.sh"), "complete").unwrap();
+    fs::write(share.join("config.toml"), "default = true").unwrap();
+
+    compile_example("uninstalls-itself");
+    let exe = get_executable("uninstalls-itself", &bin);
+
+    let mut manifest = self_replace::UninstallManifest::new();
+    manifest
+        .add_file(share.join("completions.sh"))
+        .unwrap()
+        .add_file(share.join("config.toml"))
+        .unwrap()
+        .add_dir(share.join("data"))
+        .unwrap()
+        .add_file_unchecked(share.join("missing.txt"))
+        .unwrap();
+    manifest.save(bin.join("uninstall.manifest")).unwrap();
+    assert_eq!(
+        self_replace::UninstallManifest::load(bin.join("uninstall.manifest")).unwrap(),
+        manifest
+    );
+    fs::write(share.join("config.toml"), "default = false").unwrap();
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "When I finish, I removed 2 entries and kept 1 modified files",
+    });
+    assert!(!exe.is_file());
+    assert!(!share.join("completions.sh").exists());
+    assert!(!share.join("data").exists());
+    assert!(share.join("config.toml").is_file());
+    assert_eq!(share.read_dir().unwrap().count(), 1);
+    assert!(scratchspace.path().read_dir().unwrap().next().is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_self_delete_with_links() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = tempfile::tempdir().unwrap();
+    let bin = workspace.path().join("bin");
+    let links = workspace.path().join("links");
+    fs::create_dir_all(&bin).unwrap();
+    fs::create_dir_all(&links).unwrap();
+    compile_example("deletes-itself-with-links");
+    let exe = get_executable("deletes-itself-with-links", &bin);
+    std::os::unix::fs::symlink(&exe, links.join("symlink")).unwrap();
+    fs::hard_link(&exe, links.join("hardlink")).unwrap();
+    fs::copy(&exe, links.join("copy")).unwrap();
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "When I finish, I am deleted with 2 links",
+    });
+    assert!(!exe.is_file());
+    assert!(fs::symlink_metadata(links.join("symlink")).is_err());
+    assert!(!links.join("hardlink").is_file());
+    assert!(links.join("copy").is_file());
+    assert!(scratchspace.path().read_dir().unwrap().next().is_none());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_self_delete_link_only() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = tempfile::tempdir().unwrap();
+    compile_example("deletes-its-link");
+    let exe = get_executable("deletes-its-link", workspace.path());
+    let exe_symlink = workspace.path().join("symlink");
+    std::os::unix::fs::symlink(&exe, &exe_symlink).unwrap();
+
+    run(RunOptions {
+        path: &exe_symlink,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "When I finish, the link I was invoked through is deleted",
+    });
+    assert!(exe.is_file());
+    assert!(fs::symlink_metadata(&exe_symlink).is_err());
+    assert!(scratchspace.path().read_dir().unwrap().next().is_none());
+}
+
 #[test]
 fn test_self_replace() {
     let scratchspace = tempfile::tempdir().unwrap();
@@ -177,6 +345,45 @@ fn test_self_replace() {
     assert!(scratchspace.path().read_dir().unwrap().next().is_none());
 }
 
+#[cfg(unix)]
+#[test]
+fn test_self_replace_multicall_missing_applet_dir() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-multicall");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-multicall", &workspace);
+    get_executable("hello", &workspace);
+
+    // the replacement is committed before the applets are reconciled, so failing
+    // to do so does not fail it.
+    let output = Command::new(&exe)
+        .arg(scratchspace.path().join("missing"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    assert_eq!(
+        lines.next(),
+        Some("Next time I run, I am the hello executable with 0 new and 0 removed applets")
+    );
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("Applets not reconciled: "));
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+}
+
 #[test]
 fn test_self_replace_force_exit() {
     let scratchspace = tempfile::tempdir().unwrap();
@@ -211,6 +418,184 @@ fn test_self_replace_force_exit() {
     assert!(scratchspace.path().read_dir().unwrap().next().is_none());
 }
 
+#[cfg(unix)]
+#[test]
+fn test_self_replace_with_hardlinks() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-with-hardlinks");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-with-hardlinks", &workspace);
+    get_executable("hello", &workspace);
+    let alias = workspace.join("alias");
+    fs::hard_link(&exe, &alias).unwrap();
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Next time I run, I and 1 hardlinks are the hello executable",
+    });
+    for path in [&exe, &alias] {
+        run(RunOptions {
+            path,
+            force_exit: false,
+            scratchspace: scratchspace.path(),
+            expected_output: "Hello World!",
+        });
+    }
+    assert_eq!(workspace.read_dir().unwrap().count(), 3);
+
+    fs::remove_dir_all(&workspace).unwrap();
+    assert!(scratchspace.path().read_dir().unwrap().next().is_none());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_self_replace_with_hardlinks_failing() {
+    struct Unmount<'a>(&'a Path);
+    impl Drop for Unmount<'_> {
+        fn drop(&mut self) {
+            Command::new("umount").arg(self.0).status().ok();
+        }
+    }
+
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    let shared = scratchspace.path().join("shared");
+    let mounted = scratchspace.path().join("mounted");
+    fs::create_dir_all(&workspace).unwrap();
+    fs::create_dir_all(&shared).unwrap();
+    fs::create_dir_all(&mounted).unwrap();
+
+    compile_example("replaces-itself-with-hardlinks");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-with-hardlinks", &workspace);
+    get_executable("hello", &workspace);
+    fs::hard_link(&exe, shared.join("alias")).unwrap();
+
+    // links cannot cross mount points, even if both are on the same file system,
+    // so the hardlink seen through the bind mount cannot be updated.  This requires
+    // privileges, so the test is skipped without.
+    let ok = Command::new("mount")
+        .arg("--bind")
+        .arg(&shared)
+        .arg(&mounted)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(false, |x| x.success());
+    if !ok {
+        return;
+    }
+    let _unmount = Unmount(&mounted);
+
+    let output = Command::new(&exe).arg(&mounted).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    assert_eq!(
+        lines.next(),
+        Some("Next time I run, I and 0 hardlinks are the hello executable")
+    );
+    assert!(lines.next().unwrap().starts_with(&format!(
+        "Not updated: {}: ",
+        mounted.join("alias").display()
+    )));
+    assert_eq!(lines.next(), None);
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+    assert_eq!(mounted.read_dir().unwrap().count(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_self_replace_multicall() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-multicall");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-multicall", &workspace);
+    get_executable("hello", &workspace);
+    let exe_name = exe.file_name().unwrap();
+    std::os::unix::fs::symlink(exe_name, workspace.join("tool-foo")).unwrap();
+    std::os::unix::fs::symlink(exe_name, workspace.join("tool-bar")).unwrap();
+    fs::write(workspace.join("tool-baz"), "foreign").unwrap();
+    fs::write(workspace.join("tool-qux"), "foreign").unwrap();
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output:
+            "Next time I run, I am the hello executable with 1 new and 1 removed applets",
+    });
+    assert!(fs::symlink_metadata(workspace.join("tool-bar")).is_err());
+    assert_eq!(
+        fs::read_link(workspace.join("tool-new")).unwrap(),
+        Path::new(exe_name)
+    );
+    assert_eq!(fs::read(workspace.join("tool-baz")).unwrap(), b"foreign");
+    assert_eq!(fs::read(workspace.join("tool-qux")).unwrap(), b"foreign");
+    run(RunOptions {
+        path: &workspace.join("tool-foo"),
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+
+    fs::remove_dir_all(&workspace).unwrap();
+    assert!(scratchspace.path().read_dir().unwrap().next().is_none());
+}
+
+#[test]
+fn test_self_replace_bundle() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    let update = workspace.join("update");
+    fs::create_dir_all(&update).unwrap();
+
+    compile_example("replaces-itself-bundle");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-bundle", &workspace);
+    get_executable("hello", &update);
+    fs::write(workspace.join("data.txt"), "old").unwrap();
+    fs::write(update.join("data.txt"), "new").unwrap();
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Next time I run, I am the hello executable with new data",
+    });
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+    assert_eq!(
+        fs::read_to_string(workspace.join("data.txt")).unwrap(),
+        "new"
+    );
+
+    fs::remove_dir_all(&update).unwrap();
+    wait_until(|| workspace.read_dir().unwrap().count() == 2);
+    fs::remove_dir_all(&workspace).unwrap();
+    assert!(scratchspace.path().read_dir().unwrap().next().is_none());
+}
+
 #[cfg(unix)]
 #[test]
 fn test_self_replace_through_symlink() {
@@ -257,3 +642,689 @@ fn test_self_replace_through_symlink() {
     fs::remove_dir_all(&workspace).unwrap();
     assert!(scratchspace.path().read_dir().unwrap().next().is_none());
 }
+
+#[test]
+fn test_update_after_exit() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("updates-after-exit");
+    compile_example("hello");
+
+    let exe = get_executable("updates-after-exit", &workspace);
+    let hello = get_executable("hello", &workspace);
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Once I exited, I am the hello executable",
+    });
+
+    // the helper runs detached, so the replacement happens in the background.
+    let hello_contents = fs::read(&hello).unwrap();
+    wait_until(|| fs::read(&exe).unwrap() == hello_contents);
+    wait_until(|| workspace.read_dir().unwrap().count() == 2);
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+
+    fs::remove_dir_all(&workspace).unwrap();
+    wait_until(|| scratchspace.path().read_dir().unwrap().next().is_none());
+}
+
+#[test]
+fn test_self_replace_health_check() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-health-check");
+
+    let exe = get_executable("replaces-itself-health-check", &workspace);
+    let new_version = workspace.join("new-version");
+    fs::copy(&exe, &new_version).unwrap();
+    let run_with = |arg: Option<&str>| {
+        let mut cmd = Command::new(&exe);
+        cmd.args(arg).arg(&new_version);
+        let output = cmd.output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let health_state = workspace.join(format!(
+        ".{}.health",
+        exe.file_name().unwrap().to_str().unwrap()
+    ));
+
+    // an update that is never confirmed is rolled back
+    assert_eq!(
+        run_with(Some("update")),
+        "Updated, the next version needs to confirm its health\n"
+    );
+    assert!(health_state.is_file());
+    assert_eq!(run_with(None), "Unconfirmed launch 1 of 2\nRunning\n");
+    assert_eq!(run_with(None), "Unconfirmed launch 2 of 2\nRunning\n");
+    assert_eq!(run_with(None), "Rolled back to the previous version\n");
+    assert!(!health_state.exists());
+    assert_eq!(run_with(None), "Running\n");
+
+    // a confirmed update stays
+    run_with(Some("update"));
+    assert_eq!(
+        run_with(Some("confirm")),
+        "Unconfirmed launch 1 of 2\nConfirmed\n"
+    );
+    assert!(!health_state.exists());
+    assert_eq!(run_with(None), "Running\n");
+
+    let backups = workspace.join(format!(
+        ".{}.backups",
+        exe.file_name().unwrap().to_str().unwrap()
+    ));
+    let metas = fs::read_dir(&backups)
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().map_or(false, |x| x == "meta"))
+        .collect::<Vec<_>>();
+    assert!(!metas.is_empty());
+    for meta in metas {
+        assert!(fs::read_to_string(meta)
+            .unwrap()
+            .starts_with("version 1.0.0\nsha256 "));
+    }
+}
+
+#[test]
+fn test_self_replace_with_backups() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-with-backups");
+
+    let exe = get_executable("replaces-itself-with-backups", &workspace);
+    let run_with = |args: &[&str]| {
+        let output = Command::new(&exe).args(args).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    // trailing bytes give every version a different digest
+    let mut contents = fs::read(&exe).unwrap();
+    for (version, expected) in [("1.0", 0), ("1.1", 0), ("1.2", 1), ("1.3", 1)] {
+        contents.push(b'x');
+        let new_version = workspace.join("new-version");
+        fs::write(&new_version, &contents).unwrap();
+        assert_eq!(
+            run_with(&["update", new_version.to_str().unwrap(), version]),
+            format!("Updated, pruned {expected} backups\n")
+        );
+        fs::remove_file(&new_version).unwrap();
+    }
+    assert_eq!(run_with(&[]), "1.3\n1.2\n");
+    assert_eq!(run_with(&["prune"]), "Pruned 1 backups\n");
+    assert_eq!(run_with(&[]), "1.3\n");
+}
+
+#[test]
+fn test_apply_pending_update() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("applies-pending-update");
+    compile_example("hello");
+
+    let exe = get_executable("applies-pending-update", &workspace);
+    get_executable("hello", &workspace);
+    let run_with = |args: &[&str]| {
+        let output = Command::new(&exe).args(args).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(run_with(&[]), "Nothing to do\n");
+    assert_eq!(
+        run_with(&["stage"]),
+        "Next time I run, I become the hello executable\n"
+    );
+    assert_eq!(workspace.read_dir().unwrap().count(), 4);
+    assert_eq!(run_with(&[]), "Applied pending update\n");
+    #[cfg(windows)]
+    {
+        thread::sleep(Duration::from_millis(200));
+    }
+    assert_eq!(run_with(&[]), "Hello World!\n");
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_self_replace_on_exit() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-on-exit");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-on-exit", &workspace);
+    get_executable("hello", &workspace);
+
+    let output = Command::new(&exe).arg("cancel").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "I changed my mind, I stay who I am\n"
+    );
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+
+    for force_exit in [true, false] {
+        let exe = get_executable("replaces-itself-on-exit", &workspace);
+        run(RunOptions {
+            path: &exe,
+            force_exit,
+            scratchspace: scratchspace.path(),
+            expected_output: "When I exit, I am the hello executable",
+        });
+        run(RunOptions {
+            path: &exe,
+            force_exit,
+            scratchspace: scratchspace.path(),
+            expected_output: "Hello World!",
+        });
+        assert_eq!(workspace.read_dir().unwrap().count(), 2);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_self_replace_on_exit_interrupted() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-on-exit");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-on-exit", &workspace);
+    get_executable("hello", &workspace);
+
+    let mut child = Command::new(&exe)
+        .arg("wait")
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    std::io::BufRead::read_line(
+        &mut std::io::BufReader::new(child.stdout.as_mut().unwrap()),
+        &mut line,
+    )
+    .unwrap();
+    assert_eq!(line, "When I am interrupted, I am the hello executable\n");
+    let status = Command::new("kill")
+        .arg("-INT")
+        .arg(child.id().to_string())
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(!child.wait().unwrap().success());
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_self_replace_on_exit_ignored_signal() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-on-exit");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-on-exit", &workspace);
+    get_executable("hello", &workspace);
+    let original = fs::read(&exe).unwrap();
+
+    // an ignored SIGINT (as with nohup or background jobs) must not trigger the
+    // replacement while the process keeps running.
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg("trap '' INT; exec \"$0\" wait")
+        .arg(&exe)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    std::io::BufRead::read_line(
+        &mut std::io::BufReader::new(child.stdout.as_mut().unwrap()),
+        &mut line,
+    )
+    .unwrap();
+    assert_eq!(line, "When I am interrupted, I am the hello executable\n");
+    let pid = child.id().to_string();
+    let kill = |sig: &str| {
+        let status = Command::new("kill").arg(sig).arg(&pid).status().unwrap();
+        assert!(status.success());
+    };
+    kill("-INT");
+    thread::sleep(Duration::from_millis(300));
+    assert!(child.try_wait().unwrap().is_none());
+    assert_eq!(fs::read(&exe).unwrap(), original);
+
+    kill("-TERM");
+    assert!(!child.wait().unwrap().success());
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_self_replace_hardened() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-hardened");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-hardened", &workspace);
+    get_executable("hello", &workspace);
+    let linked_dir = scratchspace.path().join("linked");
+    std::os::unix::fs::symlink(&workspace, &linked_dir).unwrap();
+    let linked_exe = linked_dir.join(exe.file_name().unwrap());
+
+    run(RunOptions {
+        path: &linked_exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Next time I run, I am the hello executable",
+    });
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_self_replace_consuming() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-consuming");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-consuming", &workspace);
+    get_executable("hello", &workspace);
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Next time I run, I am the hello executable (Rename, update left: false)",
+    });
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_self_replace_consuming_across_mounts() {
+    struct Unmount<'a>(&'a Path);
+    impl Drop for Unmount<'_> {
+        fn drop(&mut self) {
+            Command::new("umount").arg(self.0).status().ok();
+        }
+    }
+
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    let downloads = scratchspace.path().join("downloads");
+    fs::create_dir_all(&workspace).unwrap();
+    fs::create_dir_all(&downloads).unwrap();
+
+    compile_example("replaces-itself-consuming");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-consuming", &workspace);
+    get_executable("hello", &workspace);
+
+    // the downloads are on the same file system but behind a bind mount, which
+    // cannot be renamed across (EXDEV), so the update is copied instead.  This
+    // requires privileges, so the test is skipped without.
+    let ok = Command::new("mount")
+        .arg("--bind")
+        .arg(&downloads)
+        .arg(&downloads)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(false, |x| x.success());
+    if !ok {
+        return;
+    }
+    let _unmount = Unmount(&downloads);
+
+    let output = Command::new(&exe).arg(&downloads).output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("Next time I run, I am the hello executable ("));
+    assert!(!stdout.contains("Rename"));
+    assert!(stdout.ends_with(", update left: true)\n"));
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_self_replace_in_place_bind_mount() {
+    struct Unmount<'a>(&'a Path);
+    impl Drop for Unmount<'_> {
+        fn drop(&mut self) {
+            Command::new("umount").arg(self.0).status().ok();
+        }
+    }
+
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-in-place");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-in-place", &workspace);
+    get_executable("hello", &workspace);
+
+    // bind-mount a file over the executable, which makes renaming over it
+    // impossible.  This requires privileges, so the test is skipped without.
+    let source = scratchspace.path().join("source");
+    fs::copy(&exe, &source).unwrap();
+    let mounted = Command::new("mount")
+        .arg("--bind")
+        .arg(&source)
+        .arg(&exe)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(false, |x| x.success());
+    if !mounted {
+        return;
+    }
+    let _unmount = Unmount(&exe);
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "After I exit, I am overwritten with the hello executable",
+    });
+    wait_until(|| workspace.read_dir().unwrap().count() == 2);
+    wait_until(|| fs::read(&source).unwrap() == fs::read(workspace.join("hello")).unwrap());
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_self_replace_block_signals() {
+    use std::os::unix::process::ExitStatusExt;
+
+    struct Unmount<'a>(&'a Path);
+    impl Drop for Unmount<'_> {
+        fn drop(&mut self) {
+            Command::new("umount").arg(self.0).status().ok();
+        }
+    }
+
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-blocking-signals");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-blocking-signals", &workspace);
+    get_executable("hello", &workspace);
+
+    // a bind-mounted executable cannot be renamed over (EBUSY), which is retried
+    // with signals blocked.  This requires privileges, so the test is skipped
+    // without.
+    let source = scratchspace.path().join("source");
+    fs::copy(&exe, &source).unwrap();
+    let mounted = Command::new("mount")
+        .arg("--bind")
+        .arg(&source)
+        .arg(&exe)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map_or(false, |x| x.success());
+    if !mounted {
+        return;
+    }
+    let _unmount = Unmount(&exe);
+
+    let mut child = Command::new(&exe)
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdout = std::io::BufReader::new(child.stdout.take().unwrap());
+    let mut line = String::new();
+    std::io::BufRead::read_line(&mut stdout, &mut line).unwrap();
+    assert_eq!(line, "Committing\n");
+    thread::sleep(Duration::from_millis(500));
+    let status = Command::new("kill")
+        .arg("-INT")
+        .arg(child.id().to_string())
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // the signal is held back (even though the example runs a second thread which
+    // could have received it) until the commit gave up and cleaned up, and is
+    // raised again once the previous disposition is restored.
+    let status = child.wait().unwrap();
+    assert_eq!(status.signal(), Some(libc::SIGINT));
+    let mut rest = String::new();
+    std::io::Read::read_to_string(&mut stdout, &mut rest).unwrap();
+    assert_eq!(rest, "");
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_self_replace_with_progress() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-with-progress");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-with-progress", &workspace);
+    get_executable("hello", &workspace);
+
+    let output = Command::new(&exe).arg("cancel").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Not replaced: operation was cancelled\n"
+    );
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Next time I run, I am the hello executable",
+    });
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_self_replace_verified() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-verified");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-verified", &workspace);
+    get_executable("hello", &workspace);
+    let original = fs::read(&exe).unwrap();
+
+    for args in [
+        &["truncate"][..],
+        &["corrupt"],
+        &["truncate", "hardened"],
+        &["corrupt", "hardened"],
+    ] {
+        let output = Command::new(&exe).args(args).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "Not replaced: staged executable does not match the new executable\n",
+            "{args:?}"
+        );
+        assert_eq!(fs::read(&exe).unwrap(), original, "{args:?}");
+        assert_eq!(workspace.read_dir().unwrap().count(), 2, "{args:?}");
+    }
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Next time I run, I am the hello executable",
+    });
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+}
+
+#[test]
+fn test_self_replace_with_hooks() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+
+    compile_example("replaces-itself-with-hooks");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-with-hooks", &workspace);
+    get_executable("hello", &workspace);
+
+    let output = Command::new(&exe).arg("veto").output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout).trim(),
+        "pre_stage Replace\npre_commit\non_error vetoed"
+    );
+    assert_eq!(workspace.read_dir().unwrap().count(), 2);
+
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output:
+            "pre_stage Replace\npre_commit\npost_commit\nNext time I run, I am the hello executable",
+    });
+    run(RunOptions {
+        path: &exe,
+        force_exit: false,
+        scratchspace: scratchspace.path(),
+        expected_output: "Hello World!",
+    });
+}
+
+#[cfg(feature = "audit")]
+#[test]
+fn test_self_replace_with_audit_log() {
+    let scratchspace = tempfile::tempdir().unwrap();
+    let workspace = scratchspace.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+    let log = scratchspace.path().join("audit.jsonl");
+
+    compile_example("replaces-itself-with-audit-log");
+    compile_example("hello");
+
+    let exe = get_executable("replaces-itself-with-audit-log", &workspace);
+    get_executable("hello", &workspace);
+
+    for (arg, expected_output) in [
+        ("missing", "Not replaced"),
+        ("", "Next time I run, I am the hello executable"),
+    ] {
+        let output = Command::new(&exe).arg(&log).arg(arg).output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            expected_output
+        );
+    }
+
+    let history = self_replace::history(&log).unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].operation, self_replace::AuditOperation::Replace);
+    assert!(!history[0].succeeded());
+    assert_eq!(history[0].new_sha256, None);
+    assert!(history[1].succeeded());
+    assert_eq!(history[1].old_version.as_deref(), Some("1.0"));
+    assert_eq!(history[1].new_version.as_deref(), Some("2.0"));
+    assert_eq!(history[1].old_sha256, history[0].old_sha256);
+    assert_ne!(history[1].old_sha256, history[1].new_sha256);
+    assert_eq!(history[1].executable, exe.canonicalize().unwrap());
+    #[cfg(unix)]
+    assert!(history[1].user.is_some());
+
+    let lines = fs::read_to_string(&log).unwrap();
+    assert!(lines
+        .lines()
+        .next()
+        .unwrap()
+        .contains(r#""outcome":"error""#));
+    assert!(lines.lines().nth(1).unwrap().contains(r#""outcome":"ok""#));
+}
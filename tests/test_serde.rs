@@ -0,0 +1,59 @@
+#![cfg(feature = "serde")]
+use std::io;
+
+use self_replace::{
+    ErrorReport, HealthStatus, ManifestEntry, ReplaceOutcome, StagingStrategy, UninstallManifest,
+};
+
+#[test]
+fn test_serialize_outcomes() {
+    let outcome = serde_json::to_value(ReplaceOutcome::default()).unwrap();
+    assert_eq!(outcome["staging"], "copy");
+    assert_eq!(outcome["backup"], serde_json::Value::Null);
+    let outcome: ReplaceOutcome = serde_json::from_value(outcome).unwrap();
+    assert_eq!(outcome.staging, StagingStrategy::Copy);
+
+    let status = HealthStatus::Unconfirmed {
+        launches: 1,
+        max_launches: 3,
+    };
+    let value = serde_json::to_value(&status).unwrap();
+    assert_eq!(
+        value,
+        serde_json::json!({"status": "unconfirmed", "launches": 1, "max_launches": 3})
+    );
+    assert_eq!(
+        serde_json::from_value::<HealthStatus>(value).unwrap(),
+        status
+    );
+}
+
+#[test]
+fn test_serialize_manifest() {
+    let workspace = tempfile::tempdir().unwrap();
+    let mut manifest = UninstallManifest::new();
+    manifest.add_dir(workspace.path()).unwrap();
+    let value = serde_json::to_value(&manifest).unwrap();
+    assert_eq!(value["entries"][0]["type"], "dir");
+    let parsed: UninstallManifest = serde_json::from_value(value).unwrap();
+    assert_eq!(parsed, manifest);
+    assert!(matches!(parsed.entries()[0], ManifestEntry::Dir { .. }));
+}
+
+#[test]
+fn test_serialize_error_report() {
+    let err = io::Error::new(io::ErrorKind::NotFound, "no backups");
+    let value = serde_json::to_value(ErrorReport::from(&err)).unwrap();
+    assert_eq!(
+        value,
+        serde_json::json!({"kind": "NotFound", "os_error": null, "message": "no backups"})
+    );
+    let report: ErrorReport = serde_json::from_value(value).unwrap();
+    assert_eq!(report.to_string(), "no backups");
+
+    let mut outcome = serde_json::to_value(ReplaceOutcome::default()).unwrap();
+    assert_eq!(outcome["prune_error"], serde_json::Value::Null);
+    outcome["prune_error"] = serde_json::to_value(&report).unwrap();
+    let outcome: ReplaceOutcome = serde_json::from_value(outcome).unwrap();
+    assert_eq!(outcome.prune_error, Some(report));
+}
@@ -0,0 +1,37 @@
+#![cfg(unix)]
+use std::fs;
+
+use self_replace::VersionedInstall;
+
+#[test]
+fn test_versioned_install() {
+    let workspace = tempfile::tempdir().unwrap();
+    let root = workspace.path().join("tool");
+    let launcher = workspace.path().join("launcher");
+    let install = VersionedInstall::new(&root, "tool");
+
+    for version in ["1", "2", "3"] {
+        let source = workspace.path().join(format!("tool-{version}"));
+        fs::write(&source, format!("version {version}")).unwrap();
+        install.update(version, &source).unwrap();
+    }
+    install.link_launcher(&launcher).unwrap();
+    assert_eq!(install.current_version().unwrap().as_deref(), Some("3"));
+    assert_eq!(fs::read_to_string(&launcher).unwrap(), "version 3");
+    assert!(install
+        .update("3", workspace.path().join("tool-3"))
+        .is_err());
+
+    assert_eq!(install.rollback().unwrap(), "2");
+    assert_eq!(fs::read_to_string(&launcher).unwrap(), "version 2");
+    assert_eq!(install.rollback().unwrap(), "3");
+
+    // current and previous versions survive pruning
+    install.activate("1").unwrap();
+    let mut removed = install.prune(0).unwrap();
+    removed.sort();
+    assert_eq!(removed, vec!["2".to_string()]);
+    assert_eq!(install.versions().unwrap().len(), 2);
+    assert_eq!(fs::read_to_string(&launcher).unwrap(), "version 1");
+    assert!(install.activate("2").is_err());
+}